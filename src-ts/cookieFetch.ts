@@ -1,4 +1,4 @@
-import { invoke } from "https://raw.githubusercontent.com/maemon4095/tauri-plugin-bin-ipc/release/v0.3.0/src-ts/mod.ts";
+import { invokeReply } from "./error.ts";
 
 export type SameSite = "Strict" | "Lax" | "None";
 
@@ -11,41 +11,190 @@ export type CookieProps = {
     maxAge?: number;
     expires?: string;
     sameSite?: SameSite;
+    change?: CookieChange;
 };
 
+export type CookieChange = "added" | "changed" | "removed";
+
 export type Cookies = Record<string, Record<string, CookieProps>>;
 
+/** raw bytes, or a form whose `Content-Type` is set automatically */
+export type Body =
+    | Uint8Array
+    /** `application/json` */
+    | { json: unknown }
+    /** `application/x-www-form-urlencoded`. use pairs to repeat a name */
+    | { form: Record<string, string> | [string, string][] }
+    /** `multipart/form-data` */
+    | { multipart: MultipartPart[] };
+
+export type MultipartPart =
+    & { name: string; filename?: string; contentType?: string }
+    & ({ value: string } | { file: Uint8Array });
+
 export type FetchOptions = {
     method?: string;
     headers?: HeaderMap;
     cookies?: Cookies;
     redirect?: RedirectPolicy;
-    body?: Uint8Array;
+    body?: Body;
+    session?: string;
+    responseCookies?: ResponseCookies;
+    /** ignored when `stream` is set */
+    responseType?: ResponseType;
+    /** decode gzip, deflate, br and zstd bodies. defaults to true */
+    decompress?: boolean;
+    /** ignored for methods other than GET, or when the plugin has no `cache` configured */
+    cache?: CacheMode;
+    stream?: boolean;
+    upload?: number;
+    signal?: AbortSignal;
+    /** milliseconds until the whole response, including the body, is received */
+    timeout?: number;
+    /** milliseconds until the connection is established */
+    connectTimeout?: number;
+    /** milliseconds to wait for the headers and each body chunk */
+    readTimeout?: number;
+    recordRedirects?: boolean;
 };
 
-export type RedirectPolicy = "follow" | "manual" | { limit: number };
+export type ResponseCookies = "all" | "url" | "changes";
+
+/** same as `RequestCache` of the fetch standard */
+export type CacheMode =
+    | "default"
+    | "no-store"
+    | "reload"
+    | "no-cache"
+    | "force-cache"
+    | "only-if-cached";
+
+/** `text` is decoded with the charset in `Content-Type`, defaulting to UTF-8 */
+export type ResponseType = "bytes" | "text" | "json";
+
+/** At most 20 redirects are followed; `limit` may not exceed 20. */
+export type RedirectPolicy =
+    | "follow"
+    | "manual"
+    | "error"
+    | RedirectTarget
+    | { limit?: number; only?: RedirectTarget };
+
+/** Redirects to other targets are not followed and returned as the response. */
+export type RedirectTarget = "sameOrigin" | "scope";
 export type HeaderMap = { [name: string]: string[] };
 
-export type Response = {
+export type Response<B = Uint8Array> = {
     url: string;
     status: number;
     headers: HeaderMap;
     cookies: Cookies;
-    body: Uint8Array;
+    body: B;
+    redirects?: RedirectHop[];
+    /** the original `Content-Encoding` when the body was decompressed */
+    contentEncoding?: string;
+    /** bytes received before decompression. not set for streams */
+    encodedLength?: number;
 };
 
+export type RedirectHop = {
+    url: string;
+    status: number;
+    location?: string;
+    setCookies: string[];
+    cookies: Cookies;
+};
+
+export type StreamingResponse = Omit<Response, "body"> & {
+    body: ReadableStream<Uint8Array>;
+};
+
+type RawResponse = Response<unknown> & { stream?: number };
+
+export async function cookieFetch(
+    url: string,
+    options: FetchOptions & { stream: true },
+): Promise<StreamingResponse>;
+export async function cookieFetch(
+    url: string,
+    options: FetchOptions & { responseType: "text" },
+): Promise<Response<string>>;
+export async function cookieFetch(
+    url: string,
+    options: FetchOptions & { responseType: "json" },
+): Promise<Response<unknown>>;
 export async function cookieFetch(
     url: string,
     options?: FetchOptions,
-): Promise<Response> {
-    if (options !== undefined) {
-        const entries = Object.entries(options).filter(([, v]) =>
-            v !== undefined
-        );
-        options = Object.fromEntries(entries);
+): Promise<Response>;
+export async function cookieFetch(
+    url: string,
+    options?: FetchOptions,
+): Promise<Response<unknown> | StreamingResponse> {
+    const { signal, ...rest } = options ?? {};
+    signal?.throwIfAborted();
+
+    const fields = Object.fromEntries(
+        Object.entries(rest).filter(([, v]) => v !== undefined),
+    );
+    const abort = signal === undefined ? undefined : crypto.randomUUID();
+    const onAbort = () => {
+        invokeReply<boolean>("abort", { id: abort });
+    };
+    signal?.addEventListener("abort", onAbort, { once: true });
+    const release = () => signal?.removeEventListener("abort", onAbort);
+
+    let raw: RawResponse;
+    try {
+        raw = await invokeReply<RawResponse>("fetch", {
+            url,
+            options: options === undefined
+                ? undefined
+                : abort === undefined
+                ? fields
+                : { ...fields, abort },
+        });
+    } catch (e) {
+        release();
+        signal?.throwIfAborted();
+        throw e;
     }
-    return await invoke("cookie-fetch", "fetch", {
-        url,
-        options,
-    }) as Response;
+
+    const { stream, ...res } = raw;
+    if (stream === undefined) {
+        release();
+        return res;
+    }
+    return { ...res, body: bodyStream(stream, release, signal) };
+}
+
+function bodyStream(
+    stream: number,
+    release: () => void,
+    signal?: AbortSignal,
+): ReadableStream<Uint8Array> {
+    return new ReadableStream({
+        async pull(controller) {
+            let chunk: Uint8Array | null;
+            try {
+                chunk = await invokeReply<Uint8Array | null>("read_chunk", {
+                    stream,
+                });
+            } catch (e) {
+                release();
+                controller.error(signal?.aborted ? signal.reason : e);
+                return;
+            }
+            if (chunk === null) {
+                release();
+                controller.close();
+            } else {
+                controller.enqueue(chunk);
+            }
+        },
+        async cancel() {
+            release();
+            await invokeReply<boolean>("cancel", { stream });
+        },
+    });
 }
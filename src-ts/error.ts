@@ -0,0 +1,102 @@
+import { invoke } from "https://raw.githubusercontent.com/maemon4095/tauri-plugin-bin-ipc/release/v0.3.0/src-ts/mod.ts";
+
+export type CookieRejection =
+    | "invalidDomain"
+    | "domainMismatch"
+    | "publicSuffix"
+    | "secureOnInsecureScheme"
+    | "nonHttpScheme"
+    | "nonRelativeScheme"
+    | "expired"
+    | "parse"
+    | "unspecifiedDomain";
+
+export type InvalidCookie = {
+    domain: string;
+    name: string;
+    reason: CookieRejection;
+};
+
+export type RequestErrorClass =
+    | "dns"
+    | "tls"
+    | "connect"
+    | "timeout"
+    | "decode"
+    | "redirect"
+    | "body"
+    | "status"
+    | "builder"
+    | "request"
+    | "other";
+
+export type FetchErrorDetail =
+    & { message: string }
+    & (
+        | {
+            kind: "request";
+            class: RequestErrorClass;
+            url?: string;
+            status?: number;
+        }
+        | { kind: "invalidCookies"; cookies: InvalidCookie[] }
+        | { kind: "invalidUrl"; url: string }
+        | { kind: "notAllowed"; url: string }
+        | { kind: "sessionNotFound"; session: string }
+        | { kind: "sessionNotAllowed"; window: string; session: string }
+        | { kind: "sessionAlreadyExists"; session: string }
+        | { kind: "proxyNotAllowed"; window: string }
+        | { kind: "persistence" }
+        | { kind: "streamNotFound"; stream: number }
+        | { kind: "streamNotAllowed"; window: string; stream: number }
+        | { kind: "uploadNotFound"; upload: number }
+        | { kind: "uploadNotAllowed"; window: string; upload: number }
+        | { kind: "uploadClosed"; upload: number }
+        | { kind: "aborted" }
+        | { kind: "abortIdInUse"; abort: string }
+        | { kind: "connectTimeout" }
+        | { kind: "readTimeout" }
+        | { kind: "timeout" }
+        | { kind: "redirected"; url: string; status: number }
+        | { kind: "tooManyRedirects"; url: string }
+        | { kind: "redirectBodyNotReplayable"; url: string }
+        | { kind: "certificatePinMismatch"; host: string }
+        | { kind: "decompress"; encoding: string }
+        | {
+            kind: "decode";
+            url: string;
+            status: number;
+            responseType: "text" | "json";
+        }
+        | { kind: "notCached"; url: string }
+    );
+
+export type FetchErrorKind = FetchErrorDetail["kind"];
+
+export class CookieFetchError extends Error {
+    readonly detail: FetchErrorDetail;
+
+    constructor(detail: FetchErrorDetail) {
+        super(detail.message);
+        this.name = "CookieFetchError";
+        this.detail = detail;
+    }
+
+    get kind(): FetchErrorKind {
+        return this.detail.kind;
+    }
+}
+
+type Reply<T> = { ok: T } | { err: FetchErrorDetail };
+
+/** Invokes a command that answers with a `Reply`, throwing `CookieFetchError` on failure. */
+export async function invokeReply<T>(
+    command: string,
+    args: Record<string, unknown>,
+): Promise<T> {
+    const reply = await invoke("cookie-fetch", command, args) as Reply<T>;
+    if ("err" in reply) {
+        throw new CookieFetchError(reply.err);
+    }
+    return reply.ok;
+}
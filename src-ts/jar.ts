@@ -0,0 +1,52 @@
+import type { CookieProps, Cookies } from "./cookieFetch.ts";
+import { invokeReply } from "./error.ts";
+
+export type CookieFilter = {
+    domain?: string;
+    path?: string;
+    name?: string;
+};
+
+export type CookieKey = {
+    domain: string;
+    path: string;
+    name: string;
+};
+
+export async function listCookies(
+    session: string,
+    filter?: CookieFilter,
+): Promise<Cookies> {
+    return await invokeReply<Cookies>("list_cookies", { session, filter });
+}
+
+export async function getCookie(
+    session: string,
+    key: CookieKey,
+): Promise<CookieProps | null> {
+    return await invokeReply<CookieProps | null>("get_cookie", {
+        session,
+        ...key,
+    });
+}
+
+export async function setCookies(
+    session: string,
+    cookies: Cookies,
+): Promise<void> {
+    await invokeReply<null>("set_cookies", { session, cookies });
+}
+
+export async function deleteCookie(
+    session: string,
+    key: CookieKey,
+): Promise<boolean> {
+    return await invokeReply<boolean>("delete_cookie", {
+        session,
+        ...key,
+    });
+}
+
+export async function clearCookies(session: string): Promise<void> {
+    await invokeReply<null>("clear_cookies", { session });
+}
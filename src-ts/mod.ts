@@ -1,10 +1,44 @@
 export {
+    type Body,
+    type CacheMode,
     cookieFetch,
+    type CookieChange,
     type CookieProps,
     type Cookies,
     type FetchOptions,
     type HeaderMap,
+    type MultipartPart,
+    type RedirectHop,
     type RedirectPolicy,
+    type RedirectTarget,
     type Response,
+    type ResponseCookies,
+    type ResponseType,
     type SameSite,
+    type StreamingResponse,
 } from "./cookieFetch.ts";
+export {
+    closeSession,
+    createSession,
+    openSession,
+    type ProxyConfig,
+    type SessionOptions,
+} from "./session.ts";
+export {
+    clearCookies,
+    type CookieFilter,
+    type CookieKey,
+    deleteCookie,
+    getCookie,
+    listCookies,
+    setCookies,
+} from "./jar.ts";
+export { openUpload, Upload } from "./upload.ts";
+export {
+    CookieFetchError,
+    type CookieRejection,
+    type FetchErrorDetail,
+    type FetchErrorKind,
+    type InvalidCookie,
+    type RequestErrorClass,
+} from "./error.ts";
@@ -0,0 +1,31 @@
+import { invokeReply } from "./error.ts";
+
+export type ProxyConfig = {
+    /** proxy for every request. `http://`, `https://`, `socks5://` and `socks5h://` are supported */
+    all?: string;
+    http?: string;
+    https?: string;
+    auth?: { username: string; password: string };
+    /** hosts that bypass the proxy, in the same format as `NO_PROXY` */
+    noProxy?: string[];
+    /** follow `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` unless a proxy is given */
+    system?: boolean;
+};
+
+export type SessionOptions = {
+    /** overrides the proxy in the plugin config. Rejected unless the window policy sets `sessionProxy` */
+    proxy?: ProxyConfig;
+};
+
+export async function createSession(id: string, options?: SessionOptions): Promise<void> {
+    await invokeReply<null>("create_session", { id, options });
+}
+
+/** options are ignored if the session is already open */
+export async function openSession(id: string, options?: SessionOptions): Promise<boolean> {
+    return await invokeReply<boolean>("open_session", { id, options });
+}
+
+export async function closeSession(id: string): Promise<void> {
+    await invokeReply<null>("close_session", { id });
+}
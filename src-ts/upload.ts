@@ -0,0 +1,44 @@
+import { invokeReply } from "./error.ts";
+
+/** pass `id` as `FetchOptions.upload` and write while the fetch is pending */
+export class Upload {
+    readonly id: number;
+
+    constructor(id: number) {
+        this.id = id;
+    }
+
+    async write(chunk: Uint8Array): Promise<void> {
+        await invokeReply<null>("write_chunk", { upload: this.id, chunk });
+    }
+
+    async finish(): Promise<void> {
+        await invokeReply<null>("finish_upload", { upload: this.id });
+    }
+
+    async abort(): Promise<void> {
+        await invokeReply<null>("abort_upload", { upload: this.id });
+    }
+
+    async pipeFrom(stream: ReadableStream<Uint8Array>): Promise<void> {
+        const reader = stream.getReader();
+        try {
+            while (true) {
+                const { done, value } = await reader.read();
+                if (done) {
+                    break;
+                }
+                await this.write(value);
+            }
+        } catch (e) {
+            await this.abort();
+            throw e;
+        }
+        await this.finish();
+    }
+}
+
+export async function openUpload(): Promise<Upload> {
+    const id = await invokeReply<number>("open_upload", {});
+    return new Upload(id);
+}
@@ -0,0 +1,234 @@
+use reqwest::{
+    header::{self, HeaderMap},
+    StatusCode,
+};
+use std::time::{Duration, SystemTime};
+
+/// `Cache-Control`のうち、このキャッシュが解釈するディレクティブ。
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Directives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub public: bool,
+    pub max_age: Option<u64>,
+}
+
+impl Directives {
+    /// 未知のディレクティブと、値の不正な`max-age`は無視する。`Pragma: no-cache`は`no-cache`として扱う。
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = Directives::default();
+        for value in headers.get_all(header::CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for directive in value.split(',') {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+                    None => (directive, None),
+                };
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "no-store" => directives.no_store = true,
+                    "no-cache" => directives.no_cache = true,
+                    "public" => directives.public = true,
+                    "max-age" => {
+                        // 同じディレクティブが重複した場合は最も短いものを採る。
+                        if let Some(secs) = arg.and_then(|a| a.parse::<u64>().ok()) {
+                            directives.max_age =
+                                Some(directives.max_age.map_or(secs, |m| m.min(secs)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let pragma_no_cache = headers
+            .get_all(header::PRAGMA)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.eq_ignore_ascii_case("no-cache"));
+        if pragma_no_cache && !headers.contains_key(header::CACHE_CONTROL) {
+            directives.no_cache = true;
+        }
+
+        directives
+    }
+}
+
+/// 明示的な鮮度の指定がなくても保存してよい状態。RFC9110 15.1
+fn is_heuristically_cacheable(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+/// RFC9111 3に従い、GETのレスポンスを保存してよいか判断する。
+///
+/// 保存されたレスポンスはリダイレクトの処理を経ずに返されるため、3xxは保存しない。
+pub fn is_storable(status: StatusCode, request: &HeaderMap, response: &HeaderMap) -> bool {
+    if status.is_redirection() || Directives::parse(request).no_store {
+        return false;
+    }
+    let directives = Directives::parse(response);
+    if directives.no_store || varies_on_everything(response) {
+        return false;
+    }
+
+    is_heuristically_cacheable(status)
+        || directives.public
+        || directives.max_age.is_some()
+        || response.contains_key(header::EXPIRES)
+}
+
+fn varies_on_everything(response: &HeaderMap) -> bool {
+    vary_names(response).any(|name| name == "*")
+}
+
+/// `Vary`に挙げられたヘッダ名を小文字で返す。
+pub fn vary_names(response: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    response
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+}
+
+fn date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+/// 保存されたレスポンスの鮮度に関わる情報。
+pub struct Freshness<'a> {
+    pub headers: &'a HeaderMap,
+    pub status: StatusCode,
+    pub request_time: SystemTime,
+    pub response_time: SystemTime,
+}
+
+impl Freshness<'_> {
+    /// RFC9111 4.2.1
+    pub fn lifetime(&self) -> Duration {
+        let directives = Directives::parse(self.headers);
+        if let Some(max_age) = directives.max_age {
+            return Duration::from_secs(max_age);
+        }
+
+        let date_value = date(self.headers, header::DATE).unwrap_or(self.response_time);
+        if self.headers.contains_key(header::EXPIRES) {
+            // 解釈できない`Expires`は既に期限切れとみなす。
+            return date(self.headers, header::EXPIRES)
+                .and_then(|expires| expires.duration_since(date_value).ok())
+                .unwrap_or_default();
+        }
+
+        // 経験則による鮮度。最終更新からの経過時間の10%とする。RFC9111 4.2.2
+        match date(self.headers, header::LAST_MODIFIED) {
+            Some(last_modified) if is_heuristically_cacheable(self.status) => date_value
+                .duration_since(last_modified)
+                .map(|d| d / 10)
+                .unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// RFC9111 4.2.3
+    pub fn current_age(&self, now: SystemTime) -> Duration {
+        let apparent_age = date(self.headers, header::DATE)
+            .and_then(|date| self.response_time.duration_since(date).ok())
+            .unwrap_or_default();
+        let age_value = self
+            .headers
+            .get(header::AGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        let response_delay = self
+            .response_time
+            .duration_since(self.request_time)
+            .unwrap_or_default();
+        let corrected_initial_age = apparent_age.max(age_value + response_delay);
+        let resident_time = now.duration_since(self.response_time).unwrap_or_default();
+
+        corrected_initial_age + resident_time
+    }
+
+    /// 検証せずに返してよいか。`no-cache`を持つレスポンスは常に検証する。
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        !Directives::parse(self.headers).no_cache && self.current_age(now) < self.lifetime()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn compute_freshness() {
+        let response_time = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let freshness = |pairs| {
+            let headers = headers(pairs);
+            let freshness = Freshness {
+                headers: &headers,
+                status: StatusCode::OK,
+                request_time: response_time,
+                response_time,
+            };
+            (freshness.lifetime(), freshness.current_age(response_time))
+        };
+
+        assert_eq!(
+            freshness(&[("cache-control", "max-age=60, max-age=30"), ("age", "10")]),
+            (Duration::from_secs(30), Duration::from_secs(10))
+        );
+        assert_eq!(
+            freshness(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 08:50:37 GMT"),
+            ]),
+            (Duration::from_secs(60), Duration::ZERO)
+        );
+        assert_eq!(
+            freshness(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("last-modified", "Sun, 06 Nov 1994 08:32:57 GMT"),
+            ]),
+            (Duration::from_secs(100), Duration::ZERO)
+        );
+        assert_eq!(
+            freshness(&[("expires", "0")]),
+            (Duration::ZERO, Duration::ZERO)
+        );
+
+        assert!(!is_storable(
+            StatusCode::OK,
+            &HeaderMap::new(),
+            &headers(&[("vary", "Accept, *")])
+        ));
+        assert!(!is_storable(
+            StatusCode::MOVED_PERMANENTLY,
+            &HeaderMap::new(),
+            &headers(&[("cache-control", "max-age=60")])
+        ));
+        assert!(!is_storable(
+            StatusCode::CREATED,
+            &HeaderMap::new(),
+            &HeaderMap::new()
+        ));
+    }
+}
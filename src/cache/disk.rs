@@ -0,0 +1,136 @@
+use super::{CacheError, CacheKey, Entry};
+use crate::{persistence::JarStorage, session::SessionIds};
+use sha2::Digest;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// キーごとに一つのレコードへ、全ての変種を書き出す。
+///
+/// 永続化されるセッションのレスポンスのみを、jarと同じく`JarStorage`を通して保存する。
+/// 鍵が登録されている場合は`EncryptedStorage`で暗号化される。
+pub struct DiskStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    storage: Box<dyn JarStorage>,
+    sessions: SessionIds,
+    /// まだ書き出されていない最新の内容。`None`は削除。
+    pending: Mutex<HashMap<String, Option<Vec<u8>>>>,
+    /// 同じレコードへの書き込みが前後しないよう、書き出しを直列にする。
+    write_lock: Mutex<()>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Record {
+    key: CacheKey,
+    entries: Vec<Entry>,
+}
+
+/// セッションIDやURLはファイル名に使えない文字を含みうるため、キーのSHA-256をレコードの名前とする。
+fn record_name(key: &CacheKey) -> String {
+    let digest = sha2::Sha256::digest(format!(
+        "{}\n{}\n{}",
+        key.session.as_deref().unwrap_or_default(),
+        key.cookie.as_deref().unwrap_or_default(),
+        key.url
+    ));
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl DiskStore {
+    /// `sessions`は`PersistenceConfig.sessions`。
+    pub fn new(storage: Box<dyn JarStorage>, sessions: SessionIds) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                storage,
+                sessions,
+                pending: Mutex::new(HashMap::new()),
+                write_lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// セッションを指定しないリクエストのjarは永続化されないため、そのレスポンスも書き出さない。
+    pub fn persists(&self, key: &CacheKey) -> bool {
+        key.session
+            .as_deref()
+            .is_some_and(|id| self.inner.sessions.contains(id))
+    }
+
+    /// 読めないレコードと、永続化されなくなったセッションのレコードは削除する。キャッシュは失われても再取得できるため。
+    pub fn load(&self) -> Result<Vec<(CacheKey, Vec<Entry>)>, CacheError> {
+        let storage = &self.inner.storage;
+        let mut records = Vec::new();
+        for name in storage.jars().map_err(CacheError::Storage)? {
+            let record = storage
+                .load(&name)
+                .ok()
+                .flatten()
+                .and_then(|data| rmp_serde::from_slice::<Record>(&data).ok())
+                .filter(|r| self.persists(&r.key) && record_name(&r.key) == name);
+            match record {
+                Some(record) => records.push((record.key, record.entries)),
+                None => {
+                    let _ = storage.remove(&name);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// `entries`が空の場合はレコードを削除する。書き込みに失敗した場合はメモリにのみ残る。
+    ///
+    /// 書き出しはブロッキングするため、非同期ランタイムの中では`spawn_blocking`で行う。
+    pub fn save(&self, key: &CacheKey, entries: &[Entry]) {
+        if !self.persists(key) {
+            return;
+        }
+
+        let data = match entries {
+            [] => None,
+            _ => {
+                let record = Record {
+                    key: key.clone(),
+                    entries: entries.to_vec(),
+                };
+                rmp_serde::to_vec_named(&record).ok()
+            }
+        };
+        let name = record_name(key);
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .insert(name.clone(), data);
+
+        let inner = Arc::clone(&self.inner);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || inner.flush(&name));
+            }
+            Err(_) => inner.flush(&name),
+        }
+    }
+}
+
+impl Inner {
+    fn flush(&self, name: &str) {
+        let _guard = self.write_lock.lock().unwrap();
+        // 後の書き込みが先に書き出した場合は何もしない。
+        let Some(data) = self.pending.lock().unwrap().remove(name) else {
+            return;
+        };
+
+        let written = match data {
+            Some(data) => self.storage.save(name, &data),
+            None => self.storage.remove(name),
+        };
+        if written.is_err() {
+            let _ = self.storage.remove(name);
+        }
+    }
+}
@@ -0,0 +1,563 @@
+mod control;
+mod disk;
+
+use crate::cookie_fetch::HeaderMap;
+use bytes::Bytes;
+use control::Freshness;
+use reqwest::{header, StatusCode};
+use sha2::Digest;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+pub use control::{is_storable, Directives};
+pub use disk::DiskStore;
+
+/// RFC9111に従うプライベートキャッシュの設定。指定された場合のみキャッシュを用いる。
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CacheConfig {
+    /// 保持するレスポンスの数。`Vary`による変種はそれぞれ一つと数え、超えた場合は最も長く使われていないものから捨てる。
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// これより大きいボディを持つレスポンスは保存しない。
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+    /// 指定された場合、アプリのキャッシュディレクトリからの相対パスにもレスポンスを保存し、再起動後も用いる。
+    ///
+    /// `PersistenceConfig.sessions`に含まれるセッションのレスポンスのみが対象で、`persistence`が指定されていなければ書き出さない。
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+fn default_max_entries() -> usize {
+    256
+}
+
+fn default_max_body_size() -> u64 {
+    4 * 1024 * 1024
+}
+
+/// セッションを含めることで、jarの異なるリクエストの間でレスポンスが共有されないようにする。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct CacheKey {
+    /// `None`はセッションを指定しないリクエスト。
+    pub session: Option<String>,
+    /// セッションを指定しないリクエストで送られた`Cookie`のSHA-256。
+    ///
+    /// それらのリクエストはjarを共有せず、`FetchOptions.cookies`で渡されたものだけを送るため、`Cookie`ごとに分ける。
+    pub cookie: Option<String>,
+    pub url: String,
+}
+
+impl CacheKey {
+    /// `request`は実際に送られるリクエストのヘッダ。
+    pub fn new(
+        session: Option<&str>,
+        url: &reqwest::Url,
+        request: &reqwest::header::HeaderMap,
+    ) -> Self {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let cookie = match session {
+            Some(_) => None,
+            None => header_value(request, header::COOKIE.as_str()).map(|cookie| {
+                let digest = sha2::Sha256::digest(cookie.as_bytes());
+                digest.iter().map(|b| format!("{:02x}", b)).collect()
+            }),
+        };
+        Self {
+            session: session.map(str::to_string),
+            cookie,
+            url: url.into(),
+        }
+    }
+
+    /// `Cookie`に関わらず、同じセッションの同じURLを指すか。
+    fn same_resource(&self, other: &CacheKey) -> bool {
+        self.session == other.session && self.url == other.url
+    }
+}
+
+/// 保存されたレスポンス。ボディは`Content-Encoding`を復号する前の状態で保持する。
+///
+/// `Set-Cookie`は受け取ったときにjarへ加えられるため保持しない。
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// `Vary`に挙げられたリクエストヘッダと、保存時のリクエストでの値。
+    vary: Vec<(String, Option<String>)>,
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+    request_time: SystemTime,
+    response_time: SystemTime,
+}
+
+/// `Vary`の照合のため、同名のヘッダを一つの値にまとめる。
+fn header_value(headers: &reqwest::header::HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<_> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .map(str::trim)
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+impl Entry {
+    /// `request`は実際に送られたリクエストのヘッダ。`Vary`で選ばれた値を保持する。
+    pub fn new(
+        request: &reqwest::header::HeaderMap,
+        status: StatusCode,
+        headers: reqwest::header::HeaderMap,
+        body: Bytes,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> Self {
+        let mut headers = headers;
+        headers.remove(header::SET_COOKIE);
+        let vary = control::vary_names(&headers)
+            .map(|name| {
+                let value = header_value(request, &name);
+                (name, value)
+            })
+            .collect();
+
+        Self {
+            vary,
+            status: status.as_u16(),
+            headers: headers.into(),
+            body,
+            request_time,
+            response_time,
+        }
+    }
+
+    /// RFC9111 4.1
+    fn matches(&self, request: &reqwest::header::HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_value(request, name) == *value)
+    }
+
+    fn freshness(&self) -> Freshness<'_> {
+        Freshness {
+            headers: &self.headers,
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            request_time: self.request_time,
+            response_time: self.response_time,
+        }
+    }
+
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        self.freshness().is_fresh(now)
+    }
+
+    /// 検証のためのリクエストに加える`If-None-Match`と`If-Modified-Since`。検証子がなければ空。
+    pub fn validators(&self) -> reqwest::header::HeaderMap {
+        let mut validators = reqwest::header::HeaderMap::new();
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            validators.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(header::LAST_MODIFIED) {
+            validators.insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        validators
+    }
+
+    /// 304のレスポンスのヘッダで更新した複製を返す。RFC9111 4.3.4
+    pub fn freshen(
+        &self,
+        not_modified: &reqwest::header::HeaderMap,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> Self {
+        let mut headers: reqwest::header::HeaderMap = self.headers.clone().into();
+        for name in not_modified.keys() {
+            // 保存されたボディの表現に関わるヘッダは更新しない。RFC9111 3.2
+            if matches!(
+                *name,
+                header::CONTENT_LENGTH
+                    | header::CONTENT_ENCODING
+                    | header::TRANSFER_ENCODING
+                    | header::CONTENT_RANGE
+                    | header::SET_COOKIE
+            ) {
+                continue;
+            }
+            headers.remove(name);
+            for value in not_modified.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        Self {
+            vary: self.vary.clone(),
+            status: self.status,
+            headers: headers.into(),
+            body: self.body.clone(),
+            request_time,
+            response_time,
+        }
+    }
+
+    /// 保存されたレスポンスを`url`へのレスポンスとして組み立てる。`Age`は現在の経過時間に置き換える。
+    pub fn to_response(&self, url: reqwest::Url, now: SystemTime) -> reqwest::Response {
+        use reqwest::ResponseBuilderExt;
+
+        let mut response = http::Response::builder()
+            .status(self.status)
+            .url(url)
+            .body(self.body.clone())
+            .expect("stored status is valid");
+        *response.headers_mut() = self.headers.clone().into();
+        response.headers_mut().insert(
+            header::AGE,
+            self.freshness().current_age(now).as_secs().into(),
+        );
+
+        response.into()
+    }
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    /// 最後に使われた順序。小さいものから捨てる。
+    used: u64,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<CacheKey, Vec<Slot>>,
+    len: usize,
+    tick: u64,
+}
+
+impl Store {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// 上限を超えた分を捨て、変更されたキーを返す。
+    fn evict(&mut self, max_entries: usize) -> Vec<CacheKey> {
+        let mut evicted = Vec::new();
+        while self.len > max_entries {
+            let Some((key, index)) = self
+                .entries
+                .iter()
+                .flat_map(|(key, slots)| slots.iter().enumerate().map(move |(i, s)| (key, i, s)))
+                .min_by_key(|(_, _, slot)| slot.used)
+                .map(|(key, i, _)| (key.clone(), i))
+            else {
+                break;
+            };
+
+            let slots = self.entries.get_mut(&key).unwrap();
+            slots.remove(index);
+            if slots.is_empty() {
+                self.entries.remove(&key);
+            }
+            self.len -= 1;
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    fn variants(&self, key: &CacheKey) -> Vec<Entry> {
+        self.entries
+            .get(key)
+            .map(|slots| slots.iter().map(|s| Entry::clone(&s.entry)).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// 保存されたレスポンス。メモリに保持し、`CacheConfig.directory`が指定された場合はディスクにも書き出す。
+pub struct HttpCache {
+    store: Mutex<Store>,
+    config: CacheConfig,
+    disk: Option<DiskStore>,
+}
+
+impl HttpCache {
+    /// `disk`が指定された場合、そこに保存されたレスポンスを読み込む。読めないものは捨てる。
+    pub fn new(config: CacheConfig, disk: Option<DiskStore>) -> Result<Self, CacheError> {
+        let mut store = Store::default();
+        if let Some(disk) = &disk {
+            let mut entries: Vec<_> = disk
+                .load()?
+                .into_iter()
+                .flat_map(|(key, entries)| entries.into_iter().map(move |e| (key.clone(), e)))
+                .collect();
+            entries.sort_by_key(|(_, e)| e.response_time);
+            for (key, entry) in entries {
+                let used = store.next_tick();
+                store.entries.entry(key).or_default().push(Slot {
+                    entry: Arc::new(entry),
+                    used,
+                });
+                store.len += 1;
+            }
+
+            for key in store.evict(config.max_entries) {
+                disk.save(&key, &store.variants(&key));
+            }
+        }
+
+        Ok(Self {
+            store: Mutex::new(store),
+            config,
+            disk,
+        })
+    }
+
+    pub fn max_body_size(&self) -> u64 {
+        self.config.max_body_size
+    }
+
+    /// `request`のヘッダに`Vary`が一致する変種を探す。
+    pub fn lookup(
+        &self,
+        key: &CacheKey,
+        request: &reqwest::header::HeaderMap,
+    ) -> Option<Arc<Entry>> {
+        let mut store = self.store.lock().unwrap();
+        let tick = store.next_tick();
+        let slot = store
+            .entries
+            .get_mut(key)?
+            .iter_mut()
+            .find(|s| s.entry.matches(request))?;
+        slot.used = tick;
+        Some(Arc::clone(&slot.entry))
+    }
+
+    /// 同じ`Vary`の値を持つ変種を置き換える。
+    pub fn store(&self, key: CacheKey, entry: Entry) {
+        let changed = {
+            let mut store = self.store.lock().unwrap();
+            let used = store.next_tick();
+            let slots = store.entries.entry(key.clone()).or_default();
+            let before = slots.len();
+            slots.retain(|s| s.entry.vary != entry.vary);
+            let removed = before - slots.len();
+            slots.push(Slot {
+                entry: Arc::new(entry),
+                used,
+            });
+            store.len = store.len + 1 - removed;
+
+            let mut changed = store.evict(self.config.max_entries);
+            if !changed.contains(&key) {
+                changed.push(key);
+            }
+            changed
+                .into_iter()
+                .map(|key| {
+                    let variants = store.variants(&key);
+                    (key, variants)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if let Some(disk) = &self.disk {
+            for (key, variants) in changed {
+                disk.save(&key, &variants);
+            }
+        }
+    }
+
+    /// 安全でないメソッドのリクエストが成功した場合など、保存されたレスポンスを無効にする。RFC9111 4.4
+    ///
+    /// `Cookie`の異なるものも含めて、同じセッションの同じURLのレスポンスを全て捨てる。
+    pub fn remove(&self, key: &CacheKey) {
+        self.remove_where(|k| k.same_resource(key));
+    }
+
+    /// 閉じられたセッションのレスポンスを捨てる。同じIDで作り直されたセッションに引き継がないため。
+    pub fn remove_session(&self, session: &str) {
+        self.remove_where(|key| key.session.as_deref() == Some(session));
+    }
+
+    fn remove_where(&self, remove: impl Fn(&CacheKey) -> bool) {
+        let removed = {
+            let mut store = self.store.lock().unwrap();
+            let mut removed = Vec::new();
+            let mut len = 0;
+            store.entries.retain(|key, slots| {
+                if !remove(key) {
+                    return true;
+                }
+                len += slots.len();
+                removed.push(key.clone());
+                false
+            });
+            store.len -= len;
+            removed
+        };
+
+        if let Some(disk) = &self.disk {
+            for key in removed {
+                disk.save(&key, &[]);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Storage(crate::persistence::StorageError),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Storage(e) => write!(f, "failed to read the cache: {}", e),
+        }
+    }
+}
+impl std::error::Error for CacheError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn entry(vary: &'static str, body: &'static str) -> Entry {
+        let mut request = reqwest::header::HeaderMap::new();
+        request.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("ja"));
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(header::VARY, HeaderValue::from_static(vary));
+        let now = SystemTime::now();
+        Entry::new(
+            &request,
+            StatusCode::OK,
+            headers,
+            Bytes::from_static(body.as_bytes()),
+            now,
+            now,
+        )
+    }
+
+    #[test]
+    fn separate_sessions_and_variants() {
+        let cache = HttpCache::new(
+            CacheConfig {
+                max_entries: 2,
+                max_body_size: default_max_body_size(),
+                directory: None,
+            },
+            None,
+        )
+        .unwrap();
+        let url = "https://example.com/a#top".parse().unwrap();
+        let no_cookie = reqwest::header::HeaderMap::new();
+        let key = CacheKey::new(None, &url, &no_cookie);
+        cache.store(key.clone(), entry("accept-language", "ja"));
+
+        let mut request = reqwest::header::HeaderMap::new();
+        request.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("ja"));
+        assert!(cache.lookup(&key, &request).is_some());
+        assert!(cache
+            .lookup(&CacheKey::new(Some("auth"), &url, &no_cookie), &request)
+            .is_none());
+        assert!(cache
+            .lookup(&key, &reqwest::header::HeaderMap::new())
+            .is_none());
+
+        // セッションを指定しないリクエストは、送る`Cookie`ごとに分ける。
+        let mut with_cookie = reqwest::header::HeaderMap::new();
+        with_cookie.insert(header::COOKIE, HeaderValue::from_static("id=1"));
+        let cookie_key = CacheKey::new(None, &url, &with_cookie);
+        assert_ne!(cookie_key, key);
+        assert!(cache.lookup(&cookie_key, &request).is_none());
+        assert_eq!(
+            CacheKey::new(Some("auth"), &url, &with_cookie),
+            CacheKey::new(Some("auth"), &url, &no_cookie)
+        );
+
+        let other = CacheKey::new(Some("auth"), &url, &no_cookie);
+        cache.store(other.clone(), entry("accept-language", "auth"));
+        cache.lookup(&key, &request);
+        let b = "https://example.com/b".parse().unwrap();
+        cache.store(CacheKey::new(None, &b, &no_cookie), entry("", "b"));
+        assert!(cache.lookup(&key, &request).is_some());
+        assert!(cache.lookup(&other, &request).is_none());
+
+        cache.store(cookie_key.clone(), entry("accept-language", "cookie"));
+        cache.remove(&key);
+        assert!(cache.lookup(&key, &request).is_none());
+        assert!(cache.lookup(&cookie_key, &request).is_none());
+        assert!(cache.lookup(&other, &request).is_none());
+    }
+
+    #[test]
+    fn persist_only_persisted_sessions() {
+        use crate::persistence::{EncryptedStorage, FileStorage, JarStorage};
+
+        let dir = std::env::temp_dir().join(format!(
+            "tauri-plugin-cookie-fetch-cache-{}",
+            std::process::id()
+        ));
+        let disk = || {
+            let storage =
+                EncryptedStorage::new(Box::new(FileStorage::new(&dir)), Box::new(|| Ok([7; 32])));
+            DiskStore::new(
+                Box::new(storage),
+                crate::session::SessionIds::Only(vec!["main".into()]),
+            )
+        };
+        let config = CacheConfig {
+            max_entries: default_max_entries(),
+            max_body_size: default_max_body_size(),
+            directory: None,
+        };
+        let files = || FileStorage::new(&dir).jars().unwrap();
+
+        let url = "https://example.com/a".parse().unwrap();
+        let no_cookie = reqwest::header::HeaderMap::new();
+        let main = CacheKey::new(Some("main"), &url, &no_cookie);
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(header::SET_COOKIE, HeaderValue::from_static("id=1"));
+        let now = SystemTime::now();
+        let stored = Entry::new(
+            &no_cookie,
+            StatusCode::OK,
+            headers,
+            Bytes::from_static(b"secret body"),
+            now,
+            now,
+        );
+
+        let cache = HttpCache::new(config.clone(), Some(disk())).unwrap();
+        cache.store(main.clone(), stored.clone());
+        cache.store(
+            CacheKey::new(Some("temp"), &url, &no_cookie),
+            stored.clone(),
+        );
+        cache.store(CacheKey::new(None, &url, &no_cookie), stored);
+        assert_eq!(files().len(), 1);
+        let data = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| std::fs::read(e.unwrap().path()).unwrap())
+            .next()
+            .unwrap();
+        assert!(!data.windows(6).any(|w| w == b"secret"));
+
+        // 読めないレコードは読み込み時に捨てる。
+        FileStorage::new(&dir).save("broken", b"broken").unwrap();
+        let cache = HttpCache::new(config.clone(), Some(disk())).unwrap();
+        assert_eq!(files().len(), 1);
+        let entry = cache.lookup(&main, &no_cookie).unwrap();
+        assert_eq!(entry.body, Bytes::from_static(b"secret body"));
+        assert!(!entry.headers.contains_key(header::SET_COOKIE));
+
+        cache.remove_session("main");
+        assert!(files().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
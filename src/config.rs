@@ -1,7 +1,172 @@
-use crate::scope::Scope;
+use crate::{
+    cache::CacheConfig,
+    cookie_fetch::{millis_serde, FetchError, Timeouts},
+    persistence::PersistenceConfig,
+    scope::SharedScope,
+    session::{SessionIds, SessionOptions},
+    tls::TlsConfig,
+    ProxyConfig,
+};
+use std::{collections::HashMap, time::Duration};
 
 #[derive(Debug, serde::Deserialize)]
 pub struct Config {
     #[serde(default)]
-    pub scope: Scope,
+    pub scope: SharedScope,
+    #[serde(default)]
+    pub persistence: Option<PersistenceConfig>,
+    /// 既定のプロキシ。`WindowPolicy.session_proxy`を許可されたウィンドウは、セッションごとに`SessionOptions.proxy`で上書きできる。
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// 指定された場合、GETのレスポンスをセッションごとにキャッシュする。
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// `FetchOptions`で指定されなかった場合の制限時間。
+    #[serde(default, with = "millis_serde")]
+    pub timeout: Option<Duration>,
+    #[serde(default, rename = "connectTimeout", with = "millis_serde")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default, rename = "readTimeout", with = "millis_serde")]
+    pub read_timeout: Option<Duration>,
+    /// ウィンドウのラベルごとの制限。指定のないウィンドウは`scope`に従い、全てのセッションを使える。
+    #[serde(default)]
+    pub windows: HashMap<String, WindowPolicy>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowPolicy {
+    /// 指定された場合、全体の`scope`の代わりに用いる。
+    #[serde(default)]
+    pub scope: Option<SharedScope>,
+    #[serde(default)]
+    pub sessions: SessionIds,
+    /// 真の場合、セッションの作成時に`SessionOptions.proxy`でプロキシを指定できる。
+    #[serde(default)]
+    pub session_proxy: bool,
+}
+
+static ALL_SESSIONS: SessionIds = SessionIds::All;
+
+/// あるウィンドウに適用される制限。
+pub struct Policy<'a> {
+    pub window: &'a str,
+    pub scope: &'a SharedScope,
+    pub sessions: &'a SessionIds,
+    pub session_proxy: bool,
+}
+
+impl Config {
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+        }
+    }
+
+    pub fn policy<'a>(&'a self, window: &'a str) -> Policy<'a> {
+        let policy = self.windows.get(window);
+        Policy {
+            window,
+            scope: policy.and_then(|p| p.scope.as_ref()).unwrap_or(&self.scope),
+            sessions: policy.map_or(&ALL_SESSIONS, |p| &p.sessions),
+            session_proxy: policy.is_some_and(|p| p.session_proxy),
+        }
+    }
+
+    /// ホスト側から送るリクエストに適用される制限。全体の`scope`に従い、全てのセッションを使える。
+    pub fn host_policy(&self) -> Policy<'_> {
+        Policy {
+            window: "",
+            scope: &self.scope,
+            sessions: &ALL_SESSIONS,
+            session_proxy: true,
+        }
+    }
+}
+
+impl Policy<'_> {
+    pub fn check_session(&self, id: &str) -> Result<(), FetchError> {
+        if self.sessions.contains(id) {
+            Ok(())
+        } else {
+            Err(FetchError::SessionNotAllowed {
+                window: self.window.to_string(),
+                session: id.to_string(),
+            })
+        }
+    }
+
+    /// セッションを作成してよいか確かめる。
+    pub fn check_new_session(&self, id: &str, options: &SessionOptions) -> Result<(), FetchError> {
+        self.check_session(id)?;
+        if options.proxy.is_some() && !self.session_proxy {
+            return Err(FetchError::ProxyNotAllowed {
+                window: self.window.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_window_policy() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "scope": { "allowlist": ["https://*"] },
+                "windows": {
+                    "preview": {
+                        "scope": { "allowlist": [{ "host": "cdn.example.com" }] },
+                        "sessions": []
+                    },
+                    "login": { "sessionProxy": true },
+                    "tools": {}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let request = reqwest::Request::new(
+            reqwest::Method::GET,
+            "https://api.example.com/".parse().unwrap(),
+        );
+        assert!(config.policy("main").scope.allows(&request));
+        assert!(config.policy("main").check_session("auth").is_ok());
+        assert!(!config.policy("preview").scope.allows(&request));
+        assert!(matches!(
+            config.policy("preview").check_session("auth"),
+            Err(FetchError::SessionNotAllowed { .. })
+        ));
+
+        let options: SessionOptions =
+            serde_json::from_str(r#"{"proxy":{"all":"http://127.0.0.1:8080"}}"#).unwrap();
+        assert!(matches!(
+            config.policy("main").check_new_session("auth", &options),
+            Err(FetchError::ProxyNotAllowed { .. })
+        ));
+        // 独自の設定を持つウィンドウも、`sessionProxy`がなければプロキシを指定できない。
+        assert!(matches!(
+            config.policy("tools").check_new_session("auth", &options),
+            Err(FetchError::ProxyNotAllowed { .. })
+        ));
+        assert!(config
+            .policy("main")
+            .check_new_session("auth", &SessionOptions::default())
+            .is_ok());
+        assert!(config
+            .policy("login")
+            .check_new_session("auth", &options)
+            .is_ok());
+        assert!(config
+            .host_policy()
+            .check_new_session("auth", &options)
+            .is_ok());
+    }
 }
@@ -1,13 +1,36 @@
-use reqwest::redirect::{self, Attempt};
-use std::sync::{Arc, Mutex, MutexGuard};
+use crate::{tls::Tls, ProxyConfig};
+use reqwest::redirect;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+struct ClientPoolManager {
+    config: ClientConfig,
+}
+
+/// クライアントの接続の設定。
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub proxy: ProxyConfig,
+    pub tls: Tls,
+}
 
-struct ClientPoolManager;
 pub struct CookieClient {
     client: reqwest::Client,
     cookie_store: Arc<reqwest_cookie_store::CookieStoreMutex>,
-    redirect_policy: Arc<Mutex<RedirectPolicy>>,
+    config: ClientConfig,
+    /// 接続の制限時間はクライアント単位でしか設定できないため、制限時間ごとにcookie jarを共有するクライアントを作る。
+    ///
+    /// 最近使われたものを末尾に置き、`MAX_CONNECT_TIMEOUT_CLIENTS`を超えた分は先頭から捨てる。
+    connect_timeout_clients: Mutex<Vec<(Duration, reqwest::Client)>>,
 }
 
+/// 接続プールを持つクライアントが制限時間の値ごとに増え続けないよう、保持する数を制限する。
+const MAX_CONNECT_TIMEOUT_CLIENTS: usize = 4;
+
+/// 一つのリクエストが辿れるリダイレクトの数。リクエストごとに作られ、クライアント間で共有されない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RedirectPolicy {
     Follow,
     Limited(usize),
@@ -22,26 +45,49 @@ impl RedirectPolicy {
         Self::Limited(max)
     }
 
-    fn check(&mut self, attempt: Attempt<'_>) -> redirect::Action {
+    /// 次のリダイレクトに従うかを判断する。従う場合は残りの回数を一つ減らす。
+    pub fn check(&mut self) -> bool {
         match self {
-            RedirectPolicy::Follow => attempt.follow(),
+            RedirectPolicy::Follow => true,
             RedirectPolicy::Limited(n) => {
                 if *n == 0 {
-                    attempt.stop()
+                    false
                 } else {
                     *n -= 1;
-                    attempt.follow()
+                    true
                 }
             }
         }
     }
 }
 
-fn default_redirect_policy() -> RedirectPolicy {
-    RedirectPolicy::limited(10)
-}
-
 impl CookieClient {
+    pub fn new() -> Result<CookieClient, reqwest::Error> {
+        Self::with_cookie_store(reqwest_cookie_store::CookieStore::new(None))
+    }
+
+    pub fn with_cookie_store(
+        cookie_store: reqwest_cookie_store::CookieStore,
+    ) -> Result<CookieClient, reqwest::Error> {
+        Self::with_config(cookie_store, &ClientConfig::default())
+    }
+
+    pub fn with_config(
+        cookie_store: reqwest_cookie_store::CookieStore,
+        config: &ClientConfig,
+    ) -> Result<CookieClient, reqwest::Error> {
+        let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(cookie_store);
+        let cookie_store = Arc::new(cookie_store);
+        let client = build_client(&cookie_store, config, None)?;
+
+        Ok(CookieClient {
+            client,
+            cookie_store,
+            config: config.clone(),
+            connect_timeout_clients: Mutex::new(Vec::new()),
+        })
+    }
+
     pub fn request<U: reqwest::IntoUrl>(
         &self,
         method: reqwest::Method,
@@ -50,13 +96,55 @@ impl CookieClient {
         self.client.request(method, url)
     }
 
+    /// 接続に`connect_timeout`以上かかった場合に失敗するクライアントを返す。cookie jarは共有される。
+    pub fn http_client(
+        &self,
+        connect_timeout: Option<Duration>,
+    ) -> Result<reqwest::Client, reqwest::Error> {
+        let Some(connect_timeout) = connect_timeout else {
+            return Ok(self.client.clone());
+        };
+
+        let mut clients = self.connect_timeout_clients.lock().unwrap();
+        let client = match clients.iter().position(|(t, _)| *t == connect_timeout) {
+            Some(i) => clients.remove(i).1,
+            None => build_client(&self.cookie_store, &self.config, Some(connect_timeout))?,
+        };
+        clients.push((connect_timeout, client.clone()));
+        if clients.len() > MAX_CONNECT_TIMEOUT_CLIENTS {
+            clients.remove(0);
+        }
+
+        Ok(client)
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
     pub fn cookie_store<'a>(&'a self) -> MutexGuard<'a, reqwest_cookie_store::CookieStore> {
         self.cookie_store.lock().unwrap()
     }
+}
 
-    pub fn redirect_policy<'a>(&'a self) -> MutexGuard<'a, RedirectPolicy> {
-        self.redirect_policy.lock().unwrap()
+/// リダイレクトは各ホップを記録するため、`cookie_fetch`側で一つずつ辿る。
+fn build_client(
+    cookie_store: &Arc<reqwest_cookie_store::CookieStoreMutex>,
+    config: &ClientConfig,
+    connect_timeout: Option<Duration>,
+) -> Result<reqwest::Client, reqwest::Error> {
+    let builder = reqwest::Client::builder()
+        .cookie_provider(Arc::clone(cookie_store))
+        .dns_resolver(Arc::new(crate::dns::Resolver))
+        .redirect(redirect::Policy::none());
+    let builder = config.tls.apply(builder);
+    let mut builder = config.proxy.apply(builder)?;
+
+    if let Some(connect_timeout) = connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
     }
+
+    builder.build()
 }
 
 #[async_trait::async_trait]
@@ -65,32 +153,7 @@ impl deadpool::managed::Manager for ClientPoolManager {
     type Error = reqwest::Error;
 
     async fn create(&self) -> Result<Self::Type, Self::Error> {
-        let redirect_policy = default_redirect_policy();
-        let redirect_policy = Mutex::new(redirect_policy);
-        let redirect_policy = Arc::new(redirect_policy);
-
-        let cookie_store = reqwest_cookie_store::CookieStore::new(None);
-        let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(cookie_store);
-        let cookie_store = Arc::new(cookie_store);
-        let client = reqwest::Client::builder()
-            .cookie_provider(Arc::clone(&cookie_store))
-            .redirect(redirect::Policy::custom({
-                let policy = redirect_policy.clone();
-                move |a| {
-                    if let Ok(mut c) = policy.lock() {
-                        c.check(a)
-                    } else {
-                        a.stop()
-                    }
-                }
-            }))
-            .build()?;
-
-        Ok(CookieClient {
-            client,
-            cookie_store,
-            redirect_policy,
-        })
+        CookieClient::with_config(reqwest_cookie_store::CookieStore::new(None), &self.config)
     }
 
     async fn recycle(
@@ -100,8 +163,6 @@ impl deadpool::managed::Manager for ClientPoolManager {
     ) -> deadpool::managed::RecycleResult<Self::Error> {
         let mut cookie_store = value.cookie_store();
         cookie_store.clear();
-        let mut redirect_policy = value.redirect_policy();
-        *redirect_policy = default_redirect_policy();
         Ok(())
     }
 }
@@ -112,8 +173,12 @@ pub struct CookieClientPool {
 
 impl CookieClientPool {
     pub fn new() -> CookieClientPool {
+        Self::with_config(ClientConfig::default())
+    }
+
+    pub fn with_config(config: ClientConfig) -> CookieClientPool {
         Self {
-            client_pool: deadpool::managed::Pool::builder(ClientPoolManager)
+            client_pool: deadpool::managed::Pool::builder(ClientPoolManager { config })
                 .build()
                 .unwrap(),
         }
@@ -125,3 +190,26 @@ impl CookieClientPool {
         self.client_pool.get().await.unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bound_connect_timeout_clients() {
+        let client = CookieClient::new().unwrap();
+        for ms in 1..=10 {
+            client.http_client(Some(Duration::from_millis(ms))).unwrap();
+        }
+        client.http_client(Some(Duration::from_millis(7))).unwrap();
+
+        let timeouts: Vec<_> = client
+            .connect_timeout_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(t, _)| t.as_millis())
+            .collect();
+        assert_eq!(timeouts, vec![8, 9, 10, 7]);
+    }
+}
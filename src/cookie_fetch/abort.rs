@@ -0,0 +1,125 @@
+use super::{BodyStreams, FetchError};
+use futures_util::future::{AbortHandle, Abortable};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// webview側で生成される、リクエストを中断するためのトークン。
+pub type AbortId = String;
+
+/// 実行中のリクエストを`AbortId`で中断する。
+///
+/// `AbortId`はウィンドウごとに区別し、他のウィンドウが送ったリクエストは中断できない。
+pub struct Aborts {
+    requests: Mutex<HashMap<(String, AbortId), AbortState>>,
+}
+
+enum AbortState {
+    Running(AbortHandle),
+    /// リクエストの開始より先に中断が届いた。終了した後に届いた中断と区別できないため、一定時間で捨てる。
+    Aborted(Instant),
+}
+
+/// 開始されないままの中断を保持する時間。
+const ABORTED_TTL: Duration = Duration::from_secs(60);
+
+impl Aborts {
+    pub fn new() -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `id`で中断できるように`fut`を実行する。中断された場合は`FetchError::Aborted`を返す。
+    pub async fn run<T>(
+        &self,
+        window: &str,
+        id: Option<AbortId>,
+        fut: impl Future<Output = Result<T, FetchError>>,
+    ) -> Result<T, FetchError> {
+        let Some(id) = id else {
+            return fut.await;
+        };
+
+        let key = (window.to_string(), id);
+        let (handle, registration) = AbortHandle::new_pair();
+        {
+            let mut requests = self.requests.lock().unwrap();
+            match requests.entry(key.clone()) {
+                Entry::Occupied(e) => match e.get() {
+                    AbortState::Aborted(_) => {
+                        e.remove();
+                        return Err(FetchError::Aborted);
+                    }
+                    AbortState::Running(_) => return Err(FetchError::AbortIdInUse(key.1)),
+                },
+                Entry::Vacant(e) => {
+                    e.insert(AbortState::Running(handle));
+                }
+            }
+        }
+
+        let res = Abortable::new(fut, registration).await;
+
+        {
+            let mut requests = self.requests.lock().unwrap();
+            if let Some(AbortState::Running(_)) = requests.get(&key) {
+                requests.remove(&key);
+            }
+        }
+
+        res.unwrap_or(Err(FetchError::Aborted))
+    }
+
+    /// `window`が送ったリクエストと、そのリクエストが返したボディのストリームを中断する。
+    ///
+    /// どちらも見つからなければリクエストはまだ開始されていないとみなし、開始時に中断されるよう記録する。
+    pub fn abort(&self, window: &str, id: AbortId, streams: &BodyStreams) -> bool {
+        let key = (window.to_string(), id);
+        let mut requests = self.requests.lock().unwrap();
+        let running = match requests.remove(&key) {
+            Some(AbortState::Running(handle)) => {
+                handle.abort();
+                true
+            }
+            _ => false,
+        };
+        let cancelled = streams.cancel_aborted(window, &key.1);
+
+        if !running && !cancelled {
+            let now = Instant::now();
+            expire(&mut requests, now);
+            requests.insert(key, AbortState::Aborted(now));
+        }
+
+        running || cancelled
+    }
+}
+
+fn expire(requests: &mut HashMap<(String, AbortId), AbortState>, now: Instant) {
+    requests.retain(|_, state| match state {
+        AbortState::Running(_) => true,
+        AbortState::Aborted(at) => now.duration_since(*at) < ABORTED_TTL,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expire_unclaimed_aborts() {
+        let aborts = Aborts::new();
+        let streams = BodyStreams::new();
+        assert!(!aborts.abort("main", "late".to_string(), &streams));
+
+        let mut requests = aborts.requests.lock().unwrap();
+        expire(&mut requests, Instant::now());
+        assert!(requests.contains_key(&("main".to_string(), "late".to_string())));
+        expire(&mut requests, Instant::now() + ABORTED_TTL);
+        assert!(requests.is_empty());
+    }
+}
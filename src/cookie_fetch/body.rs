@@ -0,0 +1,292 @@
+/// リクエストのボディ。バイト列、もしくは`json`、`form`、`multipart`のいずれかをキーに持つオブジェクトで指定する。
+#[derive(Debug)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// `application/json`として送る。
+    Json(serde_json::Value),
+    /// `application/x-www-form-urlencoded`として送る。
+    Form(Vec<(String, String)>),
+    /// `multipart/form-data`として送る。
+    Multipart(Vec<Part>),
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::Bytes(Vec::new())
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(try_from = "RawPart")]
+pub struct Part {
+    pub name: String,
+    pub content: PartContent,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum PartContent {
+    Text(String),
+    File(Vec<u8>),
+}
+
+/// `value`と`file`のどちらか一方を持つ。
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RawPart {
+    name: String,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    file: Option<Vec<u8>>,
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    content_type: Option<String>,
+}
+
+impl TryFrom<RawPart> for Part {
+    type Error = String;
+
+    fn try_from(value: RawPart) -> Result<Self, Self::Error> {
+        let content = match (value.value, value.file) {
+            (Some(text), None) => PartContent::Text(text),
+            (None, Some(file)) => PartContent::File(file),
+            _ => {
+                return Err(format!(
+                    "part `{}` must have exactly one of `value` or `file`",
+                    value.name
+                ))
+            }
+        };
+
+        Ok(Part {
+            name: value.name,
+            content,
+            filename: value.filename,
+            content_type: value.content_type,
+        })
+    }
+}
+
+impl Body {
+    /// `builder`にボディを設定する。`Bytes`以外は`Content-Type`も設定する。
+    pub fn apply(
+        self,
+        builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, reqwest::Error> {
+        match self {
+            Body::Bytes(bytes) => Ok(builder.body(bytes)),
+            Body::Json(value) => Ok(builder.json(&value)),
+            Body::Form(fields) => Ok(builder.form(&fields)),
+            Body::Multipart(parts) => {
+                let mut form = reqwest::multipart::Form::new();
+                for part in parts {
+                    let name = part.name.clone();
+                    form = form.part(name, part.into_reqwest()?);
+                }
+                Ok(builder.multipart(form))
+            }
+        }
+    }
+}
+
+impl Part {
+    fn into_reqwest(self) -> Result<reqwest::multipart::Part, reqwest::Error> {
+        let mut part = match self.content {
+            PartContent::Text(text) => reqwest::multipart::Part::text(text),
+            PartContent::File(file) => reqwest::multipart::Part::bytes(file),
+        };
+        if let Some(filename) = self.filename {
+            part = part.file_name(filename);
+        }
+        match self.content_type {
+            Some(content_type) => part.mime_str(&content_type),
+            None => Ok(part),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Body {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        enum Key {
+            Json,
+            Form,
+            Multipart,
+        }
+        impl<'de> serde::de::Deserialize<'de> for Key {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct V;
+                impl<'de> serde::de::Visitor<'de> for V {
+                    type Value = Key;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        formatter.write_str("`json`, `form` or `multipart`")
+                    }
+
+                    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+                    where
+                        E: serde::de::Error,
+                    {
+                        match v {
+                            "json" => Ok(Key::Json),
+                            "form" => Ok(Key::Form),
+                            "multipart" => Ok(Key::Multipart),
+                            _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                        }
+                    }
+                }
+
+                deserializer.deserialize_str(V)
+            }
+        }
+
+        struct V;
+        impl<'de> serde::de::Visitor<'de> for V {
+            type Value = Body;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str(
+                    "bytes, `{ json: any }`, `{ form: {...} }` or `{ multipart: [...] }`",
+                )
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Body::Bytes(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Body::Bytes(v))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut buf = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element::<u8>()? {
+                    buf.push(b);
+                }
+                Ok(Body::Bytes(buf))
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let Some(key) = map.next_key::<Key>()? else {
+                    return Err(<A::Error as serde::de::Error>::invalid_length(0, &self));
+                };
+                let body = match key {
+                    Key::Json => Body::Json(map.next_value()?),
+                    Key::Form => Body::Form(map.next_value::<FormFields>()?.0),
+                    Key::Multipart => Body::Multipart(map.next_value()?),
+                };
+                if map.next_key::<Key>()?.is_some() {
+                    return Err(<A::Error as serde::de::Error>::invalid_length(2, &self));
+                }
+
+                Ok(body)
+            }
+        }
+
+        deserializer.deserialize_any(V)
+    }
+}
+
+/// 名前と値の組の列、もしくはオブジェクト。同じ名前を繰り返す場合は列で指定する。
+struct FormFields(Vec<(String, String)>);
+
+impl<'de> serde::Deserialize<'de> for FormFields {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct V;
+        impl<'de> serde::de::Visitor<'de> for V {
+            type Value = FormFields;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("form fields")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut fields = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(field) = seq.next_element::<(String, String)>()? {
+                    fields.push(field);
+                }
+                Ok(FormFields(fields))
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut fields = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(field) = map.next_entry::<String, String>()? {
+                    fields.push(field);
+                }
+                Ok(FormFields(fields))
+            }
+        }
+
+        deserializer.deserialize_any(V)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build(body: &str) -> reqwest::Request {
+        let body: Body = serde_json::from_str(body).unwrap();
+        let builder = reqwest::Client::new().post("https://example.com/");
+        body.apply(builder).unwrap().build().unwrap()
+    }
+
+    #[test]
+    fn encode_form() {
+        let request = build(r#"{"form":[["a","1 2"],["a","&"],["b","é"]]}"#);
+        assert_eq!(
+            request.headers()[reqwest::header::CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(
+            request.body().and_then(|b| b.as_bytes()),
+            Some(&b"a=1+2&a=%26&b=%C3%A9"[..])
+        );
+    }
+
+    #[test]
+    fn encode_multipart() {
+        let request = build(
+            r#"{"multipart":[
+                {"name":"title","value":"hello"},
+                {"name":"upload","file":[104,105],"filename":"a.txt","contentType":"text/plain"}
+            ]}"#,
+        );
+        let content_type = request.headers()[reqwest::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap();
+        assert!(content_type.starts_with("multipart/form-data; boundary="));
+
+        let part: Result<Body, _> = serde_json::from_str(r#"{"multipart":[{"name":"x"}]}"#);
+        assert!(part.is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use super::{decompress::BodyReader, AbortId, FetchError};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub type StreamId = u64;
+
+/// ヘッダの受信後にwebviewから少しずつ読み出されるレスポンスボディ。
+///
+/// IDは連番で推測できるため、リクエストを送ったウィンドウ以外からは読めない。
+///
+/// 読まれないまま`IDLE_TTL`が過ぎたものと、閉じられたウィンドウのものは破棄し、接続を解放する。
+pub struct BodyStreams {
+    next_id: AtomicU64,
+    streams: Mutex<HashMap<StreamId, BodyStream>>,
+}
+
+struct BodyStream {
+    /// リクエストを送ったウィンドウのラベル。ホスト側から送った場合は空。
+    owner: String,
+    reader: Arc<tokio::sync::Mutex<BodyReader>>,
+    /// 最後に作成もしくは読まれた時刻。
+    used: Instant,
+    abort: Option<AbortId>,
+    read_timeout: Option<Duration>,
+}
+
+/// 読まれないストリームを保持する時間。
+const IDLE_TTL: Duration = Duration::from_secs(60);
+
+impl BodyStreams {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn register(
+        &self,
+        owner: &str,
+        reader: BodyReader,
+        abort: Option<AbortId>,
+        read_timeout: Option<Duration>,
+    ) -> StreamId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut streams = self.streams.lock().unwrap();
+        expire(&mut streams, now);
+        streams.insert(
+            id,
+            BodyStream {
+                owner: owner.to_string(),
+                reader: Arc::new(tokio::sync::Mutex::new(reader)),
+                used: now,
+                abort,
+                read_timeout,
+            },
+        );
+        id
+    }
+
+    /// 次のチャンクを読む。ボディを読み終えた場合は`None`を返し、ストリームを破棄する。
+    pub async fn read_chunk(
+        &self,
+        window: &str,
+        id: StreamId,
+    ) -> Result<Option<Bytes>, FetchError> {
+        let (reader, read_timeout) = {
+            let mut streams = self.streams.lock().unwrap();
+            match streams.get_mut(&id) {
+                Some(v) if v.owner == window => {
+                    v.used = Instant::now();
+                    (Arc::clone(&v.reader), v.read_timeout)
+                }
+                Some(_) => return Err(not_allowed(window, id)),
+                None => return Err(FetchError::StreamNotFound(id)),
+            }
+        };
+
+        let mut reader = reader.lock().await;
+        let chunk = reader.chunk(read_timeout).await;
+        match chunk {
+            Ok(Some(v)) => {
+                if let Some(stream) = self.streams.lock().unwrap().get_mut(&id) {
+                    stream.used = Instant::now();
+                }
+                Ok(Some(v))
+            }
+            Ok(None) => {
+                self.remove(id);
+                Ok(None)
+            }
+            Err(e) => {
+                self.remove(id);
+                Err(e)
+            }
+        }
+    }
+
+    /// ストリームを破棄し、接続を閉じる。ストリームが存在した場合は`true`を返す。
+    pub fn cancel(&self, window: &str, id: StreamId) -> Result<bool, FetchError> {
+        let mut streams = self.streams.lock().unwrap();
+        match streams.get(&id) {
+            Some(v) if v.owner != window => Err(not_allowed(window, id)),
+            Some(_) => Ok(streams.remove(&id).is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// `window`が`abort`に紐付けたストリームを全て破棄する。
+    pub fn cancel_aborted(&self, window: &str, abort: &str) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        let len = streams.len();
+        streams.retain(|_, s| s.owner != window || s.abort.as_deref() != Some(abort));
+        streams.len() != len
+    }
+
+    /// 閉じられたウィンドウのストリームを全て破棄する。
+    pub fn close_window(&self, window: &str) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.retain(|_, s| s.owner != window);
+    }
+
+    fn remove(&self, id: StreamId) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.remove(&id);
+    }
+}
+
+fn not_allowed(window: &str, stream: StreamId) -> FetchError {
+    FetchError::StreamNotAllowed {
+        window: window.to_string(),
+        stream,
+    }
+}
+
+/// 読み出し中のストリームは残す。
+fn expire(streams: &mut HashMap<StreamId, BodyStream>, now: Instant) {
+    streams
+        .retain(|_, s| Arc::strong_count(&s.reader) > 1 || now.duration_since(s.used) < IDLE_TTL);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reader() -> BodyReader {
+        let response = http::Response::builder().body("body").unwrap();
+        BodyReader::new(response.into(), false).unwrap()
+    }
+
+    #[test]
+    fn release_idle_and_closed_streams() {
+        let streams = BodyStreams::new();
+        let idle = streams.register("main", reader(), None, None);
+        let other = streams.register("other", reader(), None, None);
+
+        {
+            let mut streams = streams.streams.lock().unwrap();
+            expire(&mut streams, Instant::now());
+            assert_eq!(streams.len(), 2);
+            streams.get_mut(&other).unwrap().used += IDLE_TTL;
+            expire(&mut streams, Instant::now() + IDLE_TTL);
+            assert!(!streams.contains_key(&idle));
+            assert!(streams.contains_key(&other));
+        }
+
+        streams.close_window("other");
+        assert!(streams.streams.lock().unwrap().is_empty());
+    }
+}
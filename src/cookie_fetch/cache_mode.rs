@@ -0,0 +1,82 @@
+/// キャッシュの使い方。fetchの`RequestCache`と同じ値を取る。`cache`が設定されていない場合は無視される。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheMode {
+    /// 新鮮なレスポンスがあれば用い、古ければ検証する。
+    #[default]
+    Default,
+    /// キャッシュを参照も更新もしない。
+    NoStore,
+    /// キャッシュを参照せずに送り、結果を保存する。
+    Reload,
+    /// 保存されたレスポンスがあれば、新鮮であっても検証する。
+    NoCache,
+    /// 古いレスポンスでも検証せずに用いる。
+    ForceCache,
+    /// 古いレスポンスでも検証せずに用い、なければ`FetchError::NotCached`で失敗する。
+    OnlyIfCached,
+}
+
+impl CacheMode {
+    /// `Default`の場合、リクエストのヘッダに従って使い方を変える。
+    ///
+    /// 利用者が条件付きリクエストや範囲リクエストを送る場合は、その結果をキャッシュと混同しないよう、どのモードでも`NoStore`とする。
+    pub fn resolve(self, headers: &reqwest::header::HeaderMap) -> Self {
+        use reqwest::header;
+
+        let conditional = [
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            header::IF_MATCH,
+            header::IF_UNMODIFIED_SINCE,
+            header::IF_RANGE,
+            header::RANGE,
+        ]
+        .iter()
+        .any(|name| headers.contains_key(name));
+        if conditional {
+            return CacheMode::NoStore;
+        }
+        if self != CacheMode::Default {
+            return self;
+        }
+
+        let directives = crate::cache::Directives::parse(headers);
+        if directives.no_store {
+            CacheMode::NoStore
+        } else if directives.no_cache || directives.max_age == Some(0) {
+            CacheMode::NoCache
+        } else {
+            CacheMode::Default
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::{self, HeaderMap, HeaderValue};
+
+    #[test]
+    fn bypass_cache_for_partial_requests() {
+        let mut range = HeaderMap::new();
+        range.insert(header::RANGE, HeaderValue::from_static("bytes=0-9"));
+        for mode in [
+            CacheMode::Default,
+            CacheMode::Reload,
+            CacheMode::NoCache,
+            CacheMode::ForceCache,
+            CacheMode::OnlyIfCached,
+        ] {
+            assert_eq!(mode.resolve(&range), CacheMode::NoStore);
+        }
+
+        let mut no_cache = HeaderMap::new();
+        no_cache.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        assert_eq!(CacheMode::Default.resolve(&no_cache), CacheMode::NoCache);
+        assert_eq!(
+            CacheMode::ForceCache.resolve(&no_cache),
+            CacheMode::ForceCache
+        );
+    }
+}
@@ -27,6 +27,65 @@ pub struct CookieProps {
     #[serde(default)]
     #[serde(with = "same_site_serde")]
     pub same_site: Option<cookie::SameSite>,
+
+    /// `ResponseCookies::Changes`でのみ設定される。
+    #[serde(default, skip_deserializing)]
+    pub change: Option<CookieChange>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieChange {
+    Added,
+    Changed,
+    Removed,
+}
+
+impl CookieProps {
+    pub fn into_raw_cookie(self, name: String) -> reqwest_cookie_store::RawCookie<'static> {
+        let mut cookie = reqwest_cookie_store::RawCookie::new(name, self.value);
+
+        if let Some(v) = self.path {
+            cookie.set_path(v);
+        }
+
+        if let Some(v) = self.domain {
+            cookie.set_domain(v);
+        }
+
+        if let Some(v) = self.http_only {
+            cookie.set_http_only(v);
+        }
+
+        if let Some(v) = self.secure {
+            cookie.set_secure(v);
+        }
+
+        cookie.set_max_age(self.max_age);
+        cookie.set_expires(self.expires);
+        cookie.set_same_site(self.same_site);
+
+        cookie
+    }
+}
+
+impl From<&reqwest_cookie_store::RawCookie<'_>> for CookieProps {
+    fn from(c: &reqwest_cookie_store::RawCookie<'_>) -> Self {
+        CookieProps {
+            value: c.value().to_string(),
+            path: c.path().map(String::from),
+            domain: c.domain().map(String::from),
+            http_only: c.http_only(),
+            secure: c.secure(),
+            max_age: c.max_age(),
+            expires: c.expires().and_then(|e| match e {
+                cookie::Expiration::DateTime(v) => Some(v),
+                cookie::Expiration::Session => None,
+            }),
+            same_site: c.same_site(),
+            change: None,
+        }
+    }
 }
 
 mod same_site_serde {
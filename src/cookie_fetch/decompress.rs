@@ -0,0 +1,304 @@
+use super::{with_read_timeout, FetchError};
+use bytes::Bytes;
+use reqwest::header::{self, HeaderMap};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// `FetchOptions.decompress`が有効で、`Accept-Encoding`が指定されていない場合に送る値。
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+enum Decoder {
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    /// HTTPの`deflate`はzlib形式。
+    Deflate(flate2::write::ZlibDecoder<Vec<u8>>),
+    Brotli(Box<brotli_decompressor::DecompressorWriter<Vec<u8>>>),
+    /// `write::Decoder`はフレームの途中で終わったことを検出できないため、`zio::Writer`を直接用いる。
+    Zstd(zstd::stream::zio::Writer<Vec<u8>, zstd::stream::raw::Decoder<'static>>),
+}
+
+impl Decoder {
+    fn new(encoding: &str) -> io::Result<Option<Self>> {
+        let decoder = match encoding {
+            "gzip" | "x-gzip" => Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new())),
+            "deflate" => Decoder::Deflate(flate2::write::ZlibDecoder::new(Vec::new())),
+            "br" => Decoder::Brotli(Box::new(brotli_decompressor::DecompressorWriter::new(
+                Vec::new(),
+                8 * 1024,
+            ))),
+            "zstd" => Decoder::Zstd(zstd::stream::zio::Writer::new(
+                Vec::new(),
+                zstd::stream::raw::Decoder::new()?,
+            )),
+            _ => return Ok(None),
+        };
+        Ok(Some(decoder))
+    }
+
+    /// `input`を復号し、これまでに得られた出力を取り出す。
+    fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match self {
+            Decoder::Gzip(d) => {
+                d.write_all(input)?;
+                d.flush()?;
+                d.get_mut()
+            }
+            Decoder::Deflate(d) => {
+                d.write_all(input)?;
+                d.flush()?;
+                d.get_mut()
+            }
+            Decoder::Brotli(d) => {
+                d.write_all(input)?;
+                d.flush()?;
+                d.get_mut()
+            }
+            Decoder::Zstd(d) => {
+                d.write_all(input)?;
+                d.flush()?;
+                d.writer_mut()
+            }
+        };
+        Ok(std::mem::take(output))
+    }
+
+    /// 残りの出力を取り出す。ストリームが途中で終わっている場合は失敗する。
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(d) => d.finish(),
+            Decoder::Deflate(d) => d.finish(),
+            Decoder::Brotli(d) => d.into_inner().map_err(|_| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "truncated brotli stream")
+            }),
+            Decoder::Zstd(mut d) => {
+                d.finish()?;
+                Ok(d.into_inner().0)
+            }
+        }
+    }
+}
+
+/// `Content-Encoding`に列挙された符号化を、適用されたのと逆の順に復号する。
+struct Decoders(Vec<(String, Decoder)>);
+
+impl Decoders {
+    /// 復号できない符号化が含まれる場合は`None`を返し、ボディをそのまま扱う。
+    fn new(content_encoding: &str) -> Result<Option<Self>, FetchError> {
+        let mut decoders = Vec::new();
+        for encoding in content_encoding.rsplit(',') {
+            let encoding = encoding.trim().to_ascii_lowercase();
+            if encoding.is_empty() || encoding == "identity" {
+                continue;
+            }
+
+            match Decoder::new(&encoding) {
+                Ok(Some(decoder)) => decoders.push((encoding, decoder)),
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(decompress_error(&encoding, e)),
+            }
+        }
+
+        Ok((!decoders.is_empty()).then_some(Decoders(decoders)))
+    }
+
+    fn write(&mut self, input: &[u8]) -> Result<Vec<u8>, FetchError> {
+        let mut data = input.to_vec();
+        for (encoding, decoder) in &mut self.0 {
+            data = decoder
+                .write(&data)
+                .map_err(|e| decompress_error(encoding, e))?;
+        }
+        Ok(data)
+    }
+
+    fn finish(self) -> Result<Vec<u8>, FetchError> {
+        let mut data = Vec::new();
+        for (encoding, mut decoder) in self.0 {
+            let mut output = decoder
+                .write(&data)
+                .map_err(|e| decompress_error(&encoding, e))?;
+            output.extend(
+                decoder
+                    .finish()
+                    .map_err(|e| decompress_error(&encoding, e))?,
+            );
+            data = output;
+        }
+        Ok(data)
+    }
+}
+
+fn decompress_error(encoding: &str, e: io::Error) -> FetchError {
+    FetchError::Decompress {
+        encoding: encoding.to_string(),
+        reason: e.to_string(),
+    }
+}
+
+/// 必要に応じて`Content-Encoding`を復号しながらボディを読む。
+pub struct BodyReader {
+    response: reqwest::Response,
+    decoders: Option<Decoders>,
+    /// 復号前に受け取ったバイト数。
+    received: u64,
+    finished: bool,
+}
+
+impl BodyReader {
+    /// `decompress`が真で、`Content-Encoding`を全て復号できる場合は復号する。
+    ///
+    /// HEADのレスポンスはボディを持たないため、呼び出し側で`decompress`を偽にする。
+    pub fn new(response: reqwest::Response, decompress: bool) -> Result<Self, FetchError> {
+        let has_body = !matches!(
+            response.status(),
+            reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::NOT_MODIFIED
+        );
+        let content_encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok());
+        let decoders = match content_encoding {
+            Some(encoding) if decompress && has_body => Decoders::new(encoding)?,
+            _ => None,
+        };
+
+        Ok(Self {
+            response,
+            decoders,
+            received: 0,
+            finished: false,
+        })
+    }
+
+    pub fn decodes(&self) -> bool {
+        self.decoders.is_some()
+    }
+
+    /// 復号する場合の、元の`Content-Encoding`。
+    pub fn content_encoding(&self) -> Option<&str> {
+        if !self.decodes() {
+            return None;
+        }
+        self.response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// 復号する場合、`Content-Encoding`と`Content-Length`は復号後のボディに当てはまらないため取り除く。
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = self.response.headers().clone();
+        if self.decodes() {
+            headers.remove(header::CONTENT_ENCODING);
+            headers.remove(header::CONTENT_LENGTH);
+        }
+        headers
+    }
+
+    /// 次のチャンクを読む。復号した結果が空のチャンクは返さない。
+    pub async fn chunk(
+        &mut self,
+        read_timeout: Option<Duration>,
+    ) -> Result<Option<Bytes>, FetchError> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+
+            let chunk = with_read_timeout(read_timeout, self.response.chunk()).await?;
+            let Some(chunk) = chunk else {
+                self.finished = true;
+                // 空のボディは符号化されていないものとして扱う。
+                let rest = match self.decoders.take() {
+                    Some(decoders) if self.received > 0 => decoders.finish()?,
+                    _ => Vec::new(),
+                };
+                return Ok((!rest.is_empty()).then(|| rest.into()));
+            };
+            self.received += chunk.len() as u64;
+
+            let Some(decoders) = &mut self.decoders else {
+                return Ok(Some(chunk));
+            };
+            let output = decoders.write(&chunk)?;
+            if !output.is_empty() {
+                return Ok(Some(output.into()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encodings(content_encoding: &str) -> Option<Vec<String>> {
+        Decoders::new(content_encoding)
+            .unwrap()
+            .map(|d| d.0.into_iter().map(|(e, _)| e).collect())
+    }
+
+    #[test]
+    fn decode_in_reverse_order() {
+        assert_eq!(
+            encodings("gzip, identity, BR"),
+            Some(vec!["br".to_string(), "gzip".to_string()])
+        );
+        assert_eq!(encodings("identity"), None);
+        assert_eq!(encodings("gzip, compress"), None);
+    }
+
+    fn decode(content_encoding: &str, body: &[u8]) -> Result<Vec<u8>, FetchError> {
+        let mut decoders = Decoders::new(content_encoding)?.unwrap();
+        // チャンクの境界をまたいでも復号できることを確かめるため、小さく分けて書き込む。
+        let mut output = Vec::new();
+        for chunk in body.chunks(7) {
+            output.extend(decoders.write(chunk)?);
+        }
+        output.extend(decoders.finish()?);
+        Ok(output)
+    }
+
+    #[test]
+    fn decode_compressed_body() {
+        let text = b"cookie-fetch ".repeat(64);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&text).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(decode("gzip", &gzip).unwrap(), text);
+
+        let zstd = zstd::stream::encode_all(&text[..], 0).unwrap();
+        let mut both = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        both.write_all(&zstd).unwrap();
+        let both = both.finish().unwrap();
+        assert_eq!(decode("zstd, gzip", &both).unwrap(), text);
+
+        assert!(matches!(
+            decode("zstd", &zstd[..zstd.len() - 4]),
+            Err(FetchError::Decompress { encoding, .. }) if encoding == "zstd"
+        ));
+        assert!(decode("gzip", &gzip[..gzip.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn skip_decoding_empty_body() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for status in [200, 204, 304] {
+            let response = http::Response::builder()
+                .status(status)
+                .header(header::CONTENT_ENCODING, "zstd")
+                .body(Vec::new())
+                .unwrap();
+            let mut reader = BodyReader::new(response.into(), true).unwrap();
+            assert_eq!(runtime.block_on(reader.chunk(None)).unwrap(), None);
+        }
+    }
+}
@@ -1,137 +1,235 @@
-use super::{CookieProps, FetchError, FetchOptions, Redirect, Response};
-use crate::{CookieClient, CookieFetchState, RedirectPolicy};
-use reqwest::RequestBuilder;
-use std::collections::HashMap;
-use tauri::{Manager, State};
-
-pub async fn fetch<R: tauri::Runtime>(
-    app: tauri::AppHandle<R>,
+use super::{
+    decompress::{self, BodyReader},
+    headermap::HeaderMap,
+    jar,
+    redirect::Redirect,
+    redirect_chain, with_read_timeout, AbortId, CacheMode, FetchError, FetchOptions, RedirectHop,
+    Response, ResponseBody, ResponseCookies, ResponseType, Timeouts,
+};
+use crate::{
+    cache::{self, CacheKey, Entry},
+    config::Policy,
+    scope::SharedScope,
+    CookieClient, CookieFetchState,
+};
+use bytes::{Bytes, BytesMut};
+use reqwest::header::{self, HeaderValue};
+use std::time::SystemTime;
+
+/// `policy`の制限の下でリクエストを送る。
+pub async fn fetch(
+    state: &CookieFetchState,
+    policy: Policy<'_>,
     url: String,
     options: Option<FetchOptions>,
 ) -> Result<Response, FetchError> {
     let url = match reqwest::Url::parse(&url) {
         Ok(v) => v,
-        Err(_) => return Err(FetchError::InvalidUrl),
+        Err(_) => return Err(FetchError::InvalidUrl(url)),
     };
 
-    let state: State<'_, CookieFetchState> = app.state();
-
-    if !state.config.scope.is_allowed(&url) {
-        return Err(FetchError::NotAllowed);
+    let session = options.as_ref().and_then(|o| o.session.clone());
+    let abort = options.as_ref().and_then(|o| o.abort.clone());
+    match session {
+        Some(id) => {
+            let client = state.session_with(&policy, &id)?;
+            let res = state
+                .aborts
+                .run(
+                    policy.window,
+                    abort,
+                    fetch_with_client(&client, state, &policy, Some(&id), url, options),
+                )
+                .await;
+            state.sessions.notify_changed(&id).await;
+            res
+        }
+        None => {
+            let client = state.client_pool.get().await;
+            state
+                .aborts
+                .run(
+                    policy.window,
+                    abort,
+                    fetch_with_client(&client, state, &policy, None, url, options),
+                )
+                .await
+        }
     }
+}
 
-    let client = state.client_pool.get().await;
+/// リクエストの送り方とレスポンスの受け取り方。
+#[derive(Default)]
+struct Receive {
+    redirect: Redirect,
+    response_cookies: ResponseCookies,
+    response_type: ResponseType,
+    decompress: bool,
+    cache: CacheMode,
+    stream: bool,
+    abort: Option<AbortId>,
+    record_redirects: bool,
+    timeouts: Timeouts,
+    /// ボディから付けられたヘッダ。スコープの`headers`と照合しない。
+    implied: Vec<header::HeaderName>,
+}
 
+async fn fetch_with_client(
+    client: &CookieClient,
+    state: &CookieFetchState,
+    policy: &Policy<'_>,
+    session: Option<&str>,
+    url: reqwest::Url,
+    options: Option<FetchOptions>,
+) -> Result<Response, FetchError> {
     let Some(options) = options else {
-        return fetch_core(&client, client.request(reqwest::Method::GET, url)).await;
+        let receive = Receive {
+            decompress: true,
+            timeouts: state.config.timeouts(),
+            ..Default::default()
+        };
+        let http = client
+            .http_client(receive.timeouts.connect_timeout)
+            .map_err(FetchError::Reqwest)?;
+        let request = http
+            .request(reqwest::Method::GET, url)
+            .build()
+            .map_err(FetchError::Reqwest)?;
+        check_scope(policy.scope, &request, &[])?;
+        return fetch_core(client, state, policy, session, &http, request, receive).await;
     };
 
-    {
-        let mut cookies_store = client.cookie_store();
-
-        let mut url_buf = reqwest::Url::parse("http://placeholder.example.com").unwrap();
-        for (domain, pairs) in options.cookies {
-            for (name, mut props) in pairs {
-                url_buf
-                    .set_host(Some(&domain))
-                    .map_err(|_| FetchError::InvalidCookieDomain(domain.clone()))?;
-
-                let mut cookie = reqwest_cookie_store::RawCookie::new(name.clone(), props.value);
-
-                if let Some(v) = &props.path {
-                    cookie.set_path(v);
-                }
-
-                if let Some(v) = &props.domain {
-                    cookie.set_domain(v);
-                }
-
-                if let Some(v) = props.http_only.take() {
-                    cookie.set_http_only(v);
-                }
-
-                if let Some(v) = props.secure.take() {
-                    cookie.set_secure(v);
-                }
-
-                cookie.set_max_age(props.max_age.take());
-                cookie.set_expires(props.expires.take());
-                cookie.set_same_site(props.same_site.take());
-
-                cookies_store
-                    .insert_raw(&cookie, &url)
-                    .map_err(|_| FetchError::InvalidCookie {
-                        domain: domain.clone(),
-                        name,
-                    })?;
-            }
+    let timeouts = options.timeouts().or(&state.config.timeouts());
+    let caller_content_type = options.headers.contains_key(header::CONTENT_TYPE);
+    let mut receive = Receive {
+        redirect: options.redirect,
+        response_cookies: options.response_cookies,
+        response_type: options.response_type,
+        decompress: options.decompress,
+        cache: options.cache,
+        stream: options.stream,
+        abort: options.abort,
+        record_redirects: options.record_redirects,
+        timeouts,
+        implied: Vec::new(),
+    };
+    let http = client
+        .http_client(receive.timeouts.connect_timeout)
+        .map_err(FetchError::Reqwest)?;
+    let builder = http
+        .request(options.method.into(), url)
+        .headers(options.headers.into());
+    // アップロードはこの関数を抜けるとき、リクエストの成否に関わらず取り除かれる。
+    let (builder, _upload) = match options.upload {
+        Some(id) => {
+            let (body, guard) = state.upload_streams.body(policy.window, id)?;
+            (builder.body(body), Some(guard))
         }
+        None => (
+            options.body.apply(builder).map_err(FetchError::Reqwest)?,
+            None,
+        ),
+    };
+    let request = builder.build().map_err(FetchError::Reqwest)?;
+    if !caller_content_type && request.headers().contains_key(header::CONTENT_TYPE) {
+        receive.implied.push(header::CONTENT_TYPE);
     }
+    check_scope(policy.scope, &request, &receive.implied)?;
 
     {
-        let mut redirect_policy = client.redirect_policy();
-        match options.redirect {
-            Redirect::Follow => *redirect_policy = RedirectPolicy::follow(),
-            Redirect::Manual => *redirect_policy = RedirectPolicy::limited(0),
-            Redirect::Limit { limit } => *redirect_policy = RedirectPolicy::limited(limit),
-        }
+        let mut cookie_store = client.cookie_store();
+        jar::insert_cookies(&mut cookie_store, options.cookies, request.url().scheme())?;
     }
 
-    let builder = client
-        .request(options.method.into(), url)
-        .headers(options.headers.into())
-        .body(options.body);
+    return fetch_core(client, state, policy, session, &http, request, receive).await;
+}
 
-    return fetch_core(&client, builder).await;
+fn check_scope(
+    scope: &SharedScope,
+    request: &reqwest::Request,
+    implied: &[header::HeaderName],
+) -> Result<(), FetchError> {
+    if scope.allows_except(request, implied) {
+        Ok(())
+    } else {
+        Err(FetchError::NotAllowed(request.url().clone()))
+    }
 }
 
 async fn fetch_core(
     client: &CookieClient,
-    request: RequestBuilder,
+    state: &CookieFetchState,
+    policy: &Policy<'_>,
+    session: Option<&str>,
+    http: &reqwest::Client,
+    request: reqwest::Request,
+    receive: Receive,
 ) -> Result<Response, FetchError> {
-    let res = match request.send().await {
-        Ok(v) => v,
-        Err(e) => return Err(FetchError::Reqwest(e)),
+    let snapshot = match receive.response_cookies {
+        ResponseCookies::Changes => Some(jar::CookieSnapshot::take(&client.cookie_store())),
+        _ => None,
     };
+    let head = request.method() == reqwest::Method::HEAD;
 
-    let cookies: HashMap<String, HashMap<String, CookieProps>> = {
-        let store = client.cookie_store();
-        let mut cookies: HashMap<String, _> = HashMap::new();
-
-        for c in store.iter_any() {
-            let Some(domain) = &c.domain.as_cow() else {
-                continue;
-            };
-
-            let pairs: &mut HashMap<_, _> = { cookies.entry(domain.to_string()).or_default() };
-
-            pairs.insert(
-                c.name().to_string(),
-                CookieProps {
-                    value: c.value().to_string(),
-                    path: c.path().map(String::from),
-                    domain: c.domain().map(String::from),
-                    http_only: c.http_only(),
-                    secure: c.secure(),
-                    max_age: c.max_age(),
-                    expires: c.expires().and_then(|e| match e {
-                        cookie::Expiration::DateTime(v) => Some(v),
-                        cookie::Expiration::Session => None,
-                    }),
-                    same_site: c.same_site(),
-                },
-            );
+    let (res, redirects) = match &state.cache {
+        Some(cache) => {
+            send_cached(
+                client,
+                cache,
+                session,
+                http,
+                request,
+                policy.scope,
+                &receive,
+            )
+            .await?
+        }
+        None => {
+            send(
+                client,
+                http,
+                request,
+                policy.scope,
+                &receive,
+                header::HeaderMap::new(),
+            )
+            .await?
         }
+    };
 
-        cookies
+    let cookies = {
+        let store = client.cookie_store();
+        match (receive.response_cookies, snapshot) {
+            (ResponseCookies::Url, _) => jar::collect_cookies(store.matches(res.url()).into_iter()),
+            (ResponseCookies::Changes, Some(snapshot)) => snapshot.diff(&store),
+            _ => jar::collect_cookies(store.iter_any()),
+        }
     };
 
     let url = res.url().to_string();
     let status = res.status().as_u16();
-    let headers = res.headers().clone().into();
-    let body = match res.bytes().await {
-        Ok(v) => v,
-        Err(e) => return Err(FetchError::Reqwest(e)),
+    let mut reader = BodyReader::new(res, receive.decompress && !head)?;
+    let headers: HeaderMap = reader.headers().into();
+    let content_encoding = reader.content_encoding().map(str::to_string);
+    let read_timeout = receive.timeouts.read_timeout;
+    let (body, stream, encoded_length) = if receive.stream {
+        let id = state
+            .body_streams
+            .register(policy.window, reader, receive.abort, read_timeout);
+        (ResponseBody::Bytes(Bytes::new()), Some(id), None)
+    } else {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = reader.chunk(read_timeout).await? {
+            body.extend_from_slice(&chunk);
+        }
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        let body = receive
+            .response_type
+            .decode(&url, status, content_type, body.freeze())?;
+        let encoded_length = reader.decodes().then(|| reader.received());
+        (body, None, encoded_length)
     };
 
     let res = Response {
@@ -140,7 +238,537 @@ async fn fetch_core(
         headers,
         cookies,
         body,
+        stream,
+        redirects,
+        content_encoding,
+        encoded_length,
     };
 
     Ok(res)
 }
+
+async fn send(
+    client: &CookieClient,
+    http: &reqwest::Client,
+    request: reqwest::Request,
+    scope: &SharedScope,
+    receive: &Receive,
+    conditional: header::HeaderMap,
+) -> Result<(reqwest::Response, Option<Vec<RedirectHop>>), FetchError> {
+    redirect_chain::send(
+        client,
+        http,
+        request,
+        redirect_chain::Follow {
+            redirect: &receive.redirect,
+            scope,
+            record: receive.record_redirects,
+            implied: &receive.implied,
+        },
+        &receive.timeouts,
+        receive.decompress,
+        conditional,
+    )
+    .await
+}
+
+/// `receive.cache`に従って保存されたレスポンスを用い、受け取ったレスポンスを保存する。
+///
+/// 保存されたレスポンスを返す場合は`Set-Cookie`をjarに加えず、記録されるリダイレクトは空になる。
+async fn send_cached(
+    client: &CookieClient,
+    cache: &cache::HttpCache,
+    session: Option<&str>,
+    http: &reqwest::Client,
+    request: reqwest::Request,
+    scope: &SharedScope,
+    receive: &Receive,
+) -> Result<(reqwest::Response, Option<Vec<RedirectHop>>), FetchError> {
+    let varying = varying_headers(client, &request, receive.decompress);
+    let key = CacheKey::new(session, request.url(), &varying);
+    if request.method() != reqwest::Method::GET {
+        let unsafe_method = !request.method().is_safe();
+        let (res, hops) = send(client, http, request, scope, receive, Default::default()).await?;
+        // RFC9111 4.4
+        if unsafe_method && (res.status().is_success() || res.status().is_redirection()) {
+            cache.remove(&key);
+        }
+        return Ok((res, hops));
+    }
+
+    let mode = receive.cache.resolve(request.headers());
+    if mode == CacheMode::NoStore {
+        return send(client, http, request, scope, receive, Default::default()).await;
+    }
+
+    let url = request.url().clone();
+    let stored = match mode {
+        CacheMode::Reload => None,
+        _ => cache.lookup(&key, &varying),
+    };
+
+    let now = SystemTime::now();
+    let hit = |entry: &Entry| {
+        let hops = receive.record_redirects.then(Vec::new);
+        Ok((entry.to_response(url.clone(), now), hops))
+    };
+    match (mode, &stored) {
+        (CacheMode::OnlyIfCached, None) => return Err(FetchError::NotCached(url.to_string())),
+        (CacheMode::ForceCache | CacheMode::OnlyIfCached, Some(entry)) => return hit(entry),
+        (CacheMode::Default, Some(entry)) if entry.is_fresh(now) => return hit(entry),
+        _ => {}
+    }
+
+    let conditional = stored.as_ref().map(|e| e.validators()).unwrap_or_default();
+    let request_time = SystemTime::now();
+    let (res, hops) = send(client, http, request, scope, receive, conditional).await?;
+    let response_time = SystemTime::now();
+
+    // リダイレクトを辿った先のレスポンスは、元のURLのものとして保存しない。
+    if CacheKey::new(session, res.url(), &varying) != key {
+        return Ok((res, hops));
+    }
+
+    if let (Some(entry), reqwest::StatusCode::NOT_MODIFIED) = (&stored, res.status()) {
+        let entry = entry.freshen(res.headers(), request_time, response_time);
+        let res = entry.to_response(url, response_time);
+        cache.store(key, entry);
+        return Ok((res, hops));
+    }
+
+    // ストリームとして受け取る場合は、保存のためにボディを読み切ることはしない。
+    let too_large = res
+        .content_length()
+        .is_some_and(|len| len > cache.max_body_size());
+    if receive.stream || too_large || !cache::is_storable(res.status(), &varying, res.headers()) {
+        return Ok((res, hops));
+    }
+
+    let status = res.status();
+    let version = res.version();
+    let headers = res.headers().clone();
+    let body = read_to_end(res, receive.timeouts.read_timeout).await?;
+    if body.len() as u64 <= cache.max_body_size() {
+        let entry = Entry::new(
+            &varying,
+            status,
+            headers.clone(),
+            body.clone(),
+            request_time,
+            response_time,
+        );
+        cache.store(key, entry);
+    }
+
+    Ok((buffered_response(url, status, version, headers, body), hops))
+}
+
+/// `Vary`の照合に用いる、実際に送られるヘッダ。jarから付けられる`Cookie`と、補われる`Accept-Encoding`を含める。
+fn varying_headers(
+    client: &CookieClient,
+    request: &reqwest::Request,
+    decompress: bool,
+) -> header::HeaderMap {
+    let mut headers = request.headers().clone();
+    if decompress {
+        headers
+            .entry(header::ACCEPT_ENCODING)
+            .or_insert(HeaderValue::from_static(decompress::ACCEPT_ENCODING));
+    }
+    if !headers.contains_key(header::COOKIE) {
+        let cookies: Vec<_> = client
+            .cookie_store()
+            .get_request_values(request.url())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        match HeaderValue::from_str(&cookies.join("; ")) {
+            Ok(value) if !cookies.is_empty() => {
+                headers.insert(header::COOKIE, value);
+            }
+            _ => {}
+        }
+    }
+    headers
+}
+
+async fn read_to_end(
+    mut res: reqwest::Response,
+    read_timeout: Option<std::time::Duration>,
+) -> Result<Bytes, FetchError> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = with_read_timeout(read_timeout, res.chunk()).await? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// 読み切ったボディから、受け取ったものと同じレスポンスを組み立て直す。
+fn buffered_response(
+    url: reqwest::Url,
+    status: reqwest::StatusCode,
+    version: reqwest::Version,
+    headers: header::HeaderMap,
+    body: Bytes,
+) -> reqwest::Response {
+    use reqwest::ResponseBuilderExt;
+
+    let mut res = http::Response::builder()
+        .status(status)
+        .version(version)
+        .url(url)
+        .body(body)
+        .expect("status and version are taken from a response");
+    *res.headers_mut() = headers;
+    res.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::Method;
+
+    /// `responses`を一つずつ別の接続で返し、受け取ったリクエストのヘッダを返す。
+    fn serve(responses: Vec<&'static str>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let mut heads = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    head.extend_from_slice(&buf[..n]);
+                }
+                write!(stream, "{}", response).unwrap();
+                heads.push(String::from_utf8(head).unwrap().to_ascii_lowercase());
+            }
+            heads
+        });
+        (port, handle)
+    }
+
+    /// `send_cached`を同期的に呼ぶ。
+    struct Cached {
+        scope: SharedScope,
+        client: CookieClient,
+        http: reqwest::Client,
+        cache: cache::HttpCache,
+        runtime: tokio::runtime::Runtime,
+        url: String,
+    }
+
+    impl Cached {
+        fn new(port: u16) -> Self {
+            let scope = SharedScope::default();
+            scope.add_allowed(crate::scope::Rule::Url(crate::scope::UrlRule {
+                port: Some(port),
+                ..Default::default()
+            }));
+            let client = CookieClient::with_config(
+                reqwest_cookie_store::CookieStore::new(None),
+                &Default::default(),
+            )
+            .unwrap();
+            let http = client.http_client(None).unwrap();
+            let cache = cache::HttpCache::new(
+                cache::CacheConfig {
+                    max_entries: 16,
+                    max_body_size: 1024,
+                    directory: None,
+                },
+                None,
+            )
+            .unwrap();
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            Self {
+                scope,
+                client,
+                http,
+                cache,
+                runtime,
+                url: format!("http://127.0.0.1:{}/a", port),
+            }
+        }
+
+        fn send(
+            &self,
+            request: reqwest::RequestBuilder,
+            receive: Receive,
+        ) -> Result<(reqwest::StatusCode, header::HeaderMap, String), FetchError> {
+            let request = request.build().unwrap();
+            self.runtime.block_on(async {
+                let (res, _) = send_cached(
+                    &self.client,
+                    &self.cache,
+                    None,
+                    &self.http,
+                    request,
+                    &self.scope,
+                    &receive,
+                )
+                .await?;
+                let status = res.status();
+                let headers = res.headers().clone();
+                Ok((status, headers, res.text().await.unwrap()))
+            })
+        }
+
+        fn get(&self, mode: CacheMode) -> Result<String, FetchError> {
+            let receive = Receive {
+                cache: mode,
+                ..Default::default()
+            };
+            self.send(self.client.request(Method::GET, &self.url), receive)
+                .map(|(_, _, body)| body)
+        }
+    }
+
+    #[test]
+    fn revalidate_and_invalidate_cache() {
+        let (port, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncache-control: max-age=0\r\nconnection: close\r\ncontent-length: 3\r\n\r\none",
+            "HTTP/1.1 304 Not Modified\r\ncache-control: max-age=60\r\nx-checked: yes\r\nconnection: close\r\n\r\n",
+            "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\ncache-control: max-age=0\r\nconnection: close\r\ncontent-length: 3\r\n\r\ntwo",
+            "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n",
+        ]);
+        let cached = Cached::new(port);
+
+        assert_eq!(cached.get(CacheMode::Default).unwrap(), "one");
+        // 古くなったレスポンスは検証し、304のヘッダで更新する。
+        let (_, headers, body) = cached
+            .send(
+                cached.client.request(Method::GET, &cached.url),
+                Receive::default(),
+            )
+            .unwrap();
+        assert_eq!(body, "one");
+        assert!(headers.contains_key("x-checked"));
+        // 更新されたレスポンスは新鮮なため送らない。
+        assert_eq!(cached.get(CacheMode::Default).unwrap(), "one");
+        assert_eq!(cached.get(CacheMode::NoCache).unwrap(), "one");
+        assert_eq!(cached.get(CacheMode::Reload).unwrap(), "two");
+        // 古いレスポンスでも検証せずに用いる。
+        assert_eq!(cached.get(CacheMode::ForceCache).unwrap(), "two");
+        // 安全でないメソッドが成功すると、保存されたレスポンスを捨てる。
+        cached
+            .send(
+                cached.client.request(Method::POST, &cached.url),
+                Receive::default(),
+            )
+            .unwrap();
+        assert!(matches!(
+            cached.get(CacheMode::OnlyIfCached),
+            Err(FetchError::NotCached(_))
+        ));
+
+        let heads = handle.join().unwrap();
+        assert!(!heads[0].contains("if-none-match"));
+        assert!(heads[1].contains("if-none-match: \"v1\""));
+        assert!(heads[2].contains("if-none-match: \"v1\""));
+        assert!(!heads[3].contains("if-none-match"));
+        assert!(heads[4].starts_with("post "));
+    }
+
+    #[test]
+    fn follow_redirects_after_manual_request() {
+        let (port, handle) = serve(vec![
+            "HTTP/1.1 301 Moved Permanently\r\nlocation: /b\r\ncache-control: max-age=60\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 301 Moved Permanently\r\nlocation: /b\r\ncache-control: max-age=60\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 1\r\n\r\nb",
+        ]);
+        let cached = Cached::new(port);
+
+        let manual = Receive {
+            redirect: Redirect::Manual,
+            ..Default::default()
+        };
+        let (status, _, _) = cached
+            .send(cached.client.request(Method::GET, &cached.url), manual)
+            .unwrap();
+        assert_eq!(status, reqwest::StatusCode::MOVED_PERMANENTLY);
+
+        // 保存された3xxを返さず、リダイレクトを辿る。
+        let (status, _, body) = cached
+            .send(
+                cached.client.request(Method::GET, &cached.url),
+                Receive::default(),
+            )
+            .unwrap();
+        assert_eq!((status, body.as_str()), (reqwest::StatusCode::OK, "b"));
+        assert_eq!(handle.join().unwrap().len(), 3);
+    }
+
+    /// セッション`main`を開いた状態。
+    fn fetch_state(
+        config: crate::config::Config,
+        persistence: Option<crate::persistence::Persistence>,
+    ) -> CookieFetchState {
+        let state = CookieFetchState {
+            client_pool: crate::CookieClientPool::new(),
+            sessions: crate::session::Sessions::new(persistence, Default::default()),
+            body_streams: super::super::BodyStreams::new(),
+            upload_streams: super::super::UploadStreams::new(),
+            aborts: super::super::Aborts::new(),
+            cache: None,
+            config,
+        };
+        state
+            .sessions
+            .create("main".to_string(), Default::default())
+            .unwrap();
+        state
+    }
+
+    /// ウィンドウ`main`から`port`へ`options`でfetchする。
+    fn fetch_main(
+        state: &CookieFetchState,
+        port: u16,
+        options: &str,
+    ) -> Result<Response, FetchError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(fetch(
+            state,
+            state.config.policy("main"),
+            format!("http://127.0.0.1:{}/", port),
+            Some(serde_json::from_str(options).unwrap()),
+        ))
+    }
+
+    #[test]
+    fn select_response_cookies() {
+        use super::super::CookieChange;
+
+        let (port, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\nset-cookie: new=1\r\nset-cookie: old=2\r\nset-cookie: gone=; Max-Age=0\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+        ]);
+        let config = serde_json::from_str(&format!(
+            r#"{{"scope": {{"allowlist": [{{"port": {}}}]}}}}"#,
+            port
+        ))
+        .unwrap();
+        let state = fetch_state(config, None);
+        {
+            let client = state.sessions.get("main").unwrap();
+            let mut store = client.cookie_store();
+            let local = reqwest::Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap();
+            let other = reqwest::Url::parse("https://example.com/").unwrap();
+            for cookie in ["keep=1", "old=1", "gone=1"] {
+                store.parse(cookie, &local).unwrap();
+            }
+            store.parse("other=1", &other).unwrap();
+        }
+
+        let changes = fetch_main(
+            &state,
+            port,
+            r#"{"session": "main", "responseCookies": "changes"}"#,
+        )
+        .unwrap()
+        .cookies;
+        let all = fetch_main(&state, port, r#"{"session": "main"}"#)
+            .unwrap()
+            .cookies;
+        let url = fetch_main(
+            &state,
+            port,
+            r#"{"session": "main", "responseCookies": "url"}"#,
+        )
+        .unwrap()
+        .cookies;
+        handle.join().unwrap();
+
+        let local = &changes["127.0.0.1"];
+        assert_eq!(changes.len(), 1);
+        assert_eq!(local.len(), 3);
+        assert!(matches!(local["new"].change, Some(CookieChange::Added)));
+        assert!(matches!(local["old"].change, Some(CookieChange::Changed)));
+        assert_eq!(local["old"].value, "2");
+        assert!(matches!(local["gone"].change, Some(CookieChange::Removed)));
+
+        for name in ["keep", "new", "old"] {
+            assert!(all["127.0.0.1"].contains_key(name));
+        }
+        assert!(all["example.com"].contains_key("other"));
+        assert!(all
+            .values()
+            .flat_map(|c| c.values())
+            .all(|c| c.change.is_none()));
+
+        assert_eq!(url.len(), 1);
+        assert_eq!(url["127.0.0.1"].len(), 3);
+    }
+
+    #[test]
+    fn report_persistence_failures() {
+        use crate::persistence::{JarStorage, Persistence, StorageError};
+        use std::sync::{Arc, Mutex};
+
+        struct FailingStorage;
+
+        impl JarStorage for FailingStorage {
+            fn load(&self, _: &str) -> Result<Option<Vec<u8>>, StorageError> {
+                Ok(None)
+            }
+
+            fn save(&self, _: &str, _: &[u8]) -> Result<(), StorageError> {
+                Err(StorageError::Other("disk full".into()))
+            }
+
+            fn remove(&self, _: &str) -> Result<(), StorageError> {
+                Ok(())
+            }
+
+            fn jars(&self) -> Result<Vec<String>, StorageError> {
+                Ok(Vec::new())
+            }
+        }
+
+        let (port, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\nset-cookie: id=1; Max-Age=3600\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok",
+        ]);
+        let config: crate::config::Config = serde_json::from_str(&format!(
+            r#"{{"scope": {{"allowlist": [{{"port": {}}}]}}, "persistence": {{}}}}"#,
+            port
+        ))
+        .unwrap();
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let on_error = {
+            let reported = Arc::clone(&reported);
+            move |jar: &str, e: &crate::PersistenceError| {
+                reported
+                    .lock()
+                    .unwrap()
+                    .push((jar.to_string(), e.to_string()));
+            }
+        };
+        let persistence = Persistence::new(
+            Box::new(FailingStorage),
+            config.persistence.clone().unwrap(),
+        )
+        .with_error_handler(Some(Box::new(on_error)));
+        let state = fetch_state(config, Some(persistence));
+        let res = fetch_main(&state, port, r#"{"session": "main"}"#);
+        handle.join().unwrap();
+
+        assert_eq!(res.unwrap().status, 200);
+        assert_eq!(
+            *reported.lock().unwrap(),
+            [("main".to_string(), "disk full".to_string())]
+        );
+    }
+}
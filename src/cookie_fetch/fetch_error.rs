@@ -1,25 +1,512 @@
+use super::{AbortId, ResponseType, StreamId, UploadId};
+use crate::session::SessionError;
+use serde::ser::SerializeMap;
+
 #[derive(Debug)]
 pub enum FetchError {
     Reqwest(reqwest::Error),
-    InvalidCookieDomain(String),
-    InvalidCookie { domain: String, name: String },
-    InvalidUrl,
-    NotAllowed,
+    InvalidCookies(Vec<InvalidCookie>),
+    InvalidUrl(String),
+    NotAllowed(reqwest::Url),
+    SessionNotFound(String),
+    /// ウィンドウに許可されていないセッションを使おうとした。
+    SessionNotAllowed {
+        window: String,
+        session: String,
+    },
+    /// セッションごとのプロキシを許可されていないウィンドウが指定した。
+    ProxyNotAllowed {
+        window: String,
+    },
+    Session(SessionError),
+    StreamNotFound(StreamId),
+    /// ストリームを作ったウィンドウ以外から操作しようとした。
+    StreamNotAllowed {
+        window: String,
+        stream: StreamId,
+    },
+    UploadNotFound(UploadId),
+    UploadNotAllowed {
+        window: String,
+        upload: UploadId,
+    },
+    UploadClosed(UploadId),
+    Aborted,
+    AbortIdInUse(AbortId),
+    /// 制限時間内に接続できなかった。
+    ConnectTimeout,
+    /// ヘッダ、もしくはボディのチャンクが制限時間内に届かなかった。
+    ReadTimeout,
+    /// リクエスト全体が制限時間内に終わらなかった。
+    Timeout,
+    /// `Redirect::Error`でリダイレクトを受け取った。
+    Redirected {
+        url: String,
+        status: u16,
+    },
+    TooManyRedirects(String),
+    /// ストリームで送ったボディを、307/308のリダイレクト先へ送り直せない。
+    RedirectBodyNotReplayable(String),
+    /// サーバの公開鍵がホストに設定されたピンと一致しない。
+    CertificatePinMismatch(String),
+    /// `Content-Encoding`を復号できなかった。
+    Decompress {
+        encoding: String,
+        reason: String,
+    },
+    /// ボディを`response_type`の形式に復号できなかった。`status`はレスポンスのステータス。
+    Decode {
+        url: String,
+        status: u16,
+        response_type: ResponseType,
+        reason: String,
+    },
+    /// `CacheMode::OnlyIfCached`で、保存されたレスポンスがなかった。
+    NotCached(String),
+}
+
+impl FetchError {
+    /// 制限時間による失敗をそれぞれの変種に振り分ける。
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        if let Some(host) = crate::tls::pin_mismatch(&e) {
+            FetchError::CertificatePinMismatch(host)
+        } else if !e.is_timeout() {
+            FetchError::Reqwest(e)
+        } else if e.is_connect() {
+            FetchError::ConnectTimeout
+        } else {
+            FetchError::Timeout
+        }
+    }
 }
 
 impl std::fmt::Display for FetchError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            FetchError::NotAllowed => f.write_str("url not allowed on the configured scope"),
-            FetchError::InvalidCookieDomain(domain) => {
-                write!(f, "invalid cookie domain `{}`", domain)
+            FetchError::NotAllowed(url) => {
+                write!(f, "url `{}` not allowed on the configured scope", url)
             }
-            FetchError::InvalidCookie { domain, name } => {
-                write!(f, "invalid cookie `{}` of domain `{}`", name, domain)
+            FetchError::InvalidCookies(cookies) => {
+                f.write_str("invalid cookies: ")?;
+                for (i, c) in cookies.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                Ok(())
             }
-            FetchError::InvalidUrl => f.write_str("invalid url"),
+            FetchError::InvalidUrl(url) => write!(f, "invalid url `{}`", url),
             FetchError::Reqwest(e) => <_ as std::fmt::Display>::fmt(e, f),
+            FetchError::SessionNotFound(id) => write!(f, "session `{}` not found", id),
+            FetchError::SessionNotAllowed { window, session } => write!(
+                f,
+                "session `{}` is not allowed for window `{}`",
+                session, window
+            ),
+            FetchError::Session(e) => <_ as std::fmt::Display>::fmt(e, f),
+            FetchError::ProxyNotAllowed { window } => {
+                write!(f, "session proxy is not allowed for window `{}`", window)
+            }
+            FetchError::StreamNotFound(id) => write!(f, "body stream `{}` not found", id),
+            FetchError::StreamNotAllowed { window, stream } => write!(
+                f,
+                "body stream `{}` is not allowed for window `{}`",
+                stream, window
+            ),
+            FetchError::UploadNotFound(id) => write!(f, "upload `{}` not found", id),
+            FetchError::UploadNotAllowed { window, upload } => write!(
+                f,
+                "upload `{}` is not allowed for window `{}`",
+                upload, window
+            ),
+            FetchError::UploadClosed(id) => {
+                write!(f, "upload `{}` is no longer read by any request", id)
+            }
+            FetchError::Aborted => f.write_str("request aborted"),
+            FetchError::AbortIdInUse(id) => {
+                write!(f, "abort id `{}` is already used by another request", id)
+            }
+            FetchError::ConnectTimeout => f.write_str("connect timed out"),
+            FetchError::ReadTimeout => f.write_str("read timed out"),
+            FetchError::Timeout => f.write_str("request timed out"),
+            FetchError::Redirected { url, status } => {
+                write!(f, "`{}` redirected with status {}", url, status)
+            }
+            FetchError::TooManyRedirects(url) => write!(f, "too many redirects at `{}`", url),
+            FetchError::RedirectBodyNotReplayable(url) => write!(
+                f,
+                "cannot resend the streamed body to the redirect from `{}`",
+                url
+            ),
+            FetchError::CertificatePinMismatch(host) => {
+                write!(
+                    f,
+                    "certificate of `{}` does not match the pinned keys",
+                    host
+                )
+            }
+            FetchError::Decompress { encoding, reason } => {
+                write!(f, "failed to decompress {} body: {}", encoding, reason)
+            }
+            FetchError::Decode {
+                url,
+                status,
+                response_type,
+                reason,
+            } => write!(
+                f,
+                "failed to decode the body of `{}` ({}) as {}: {}",
+                url,
+                status,
+                response_type.as_str(),
+                reason
+            ),
+            FetchError::NotCached(url) => write!(f, "`{}` is not in the cache", url),
         }
     }
 }
 impl std::error::Error for FetchError {}
+
+/// `kind`で種類を判別できるオブジェクトとしてシリアライズする。`message`は常に含まれる。
+impl serde::Serialize for FetchError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        match self {
+            FetchError::Reqwest(e) => serialize_reqwest_error(&mut map, e)?,
+            FetchError::InvalidCookies(cookies) => {
+                map.serialize_entry("kind", "invalidCookies")?;
+                map.serialize_entry("cookies", cookies)?;
+            }
+            FetchError::InvalidUrl(url) => {
+                map.serialize_entry("kind", "invalidUrl")?;
+                map.serialize_entry("url", url)?;
+            }
+            FetchError::NotAllowed(url) => {
+                map.serialize_entry("kind", "notAllowed")?;
+                map.serialize_entry("url", url.as_str())?;
+            }
+            FetchError::SessionNotFound(id) | FetchError::Session(SessionError::NotFound(id)) => {
+                map.serialize_entry("kind", "sessionNotFound")?;
+                map.serialize_entry("session", id)?;
+            }
+            FetchError::SessionNotAllowed { window, session } => {
+                map.serialize_entry("kind", "sessionNotAllowed")?;
+                map.serialize_entry("window", window)?;
+                map.serialize_entry("session", session)?;
+            }
+            FetchError::Session(SessionError::AlreadyExists(id)) => {
+                map.serialize_entry("kind", "sessionAlreadyExists")?;
+                map.serialize_entry("session", id)?;
+            }
+            FetchError::Session(SessionError::Persistence(_)) => {
+                map.serialize_entry("kind", "persistence")?;
+            }
+            FetchError::Session(SessionError::Reqwest(e)) => serialize_reqwest_error(&mut map, e)?,
+            FetchError::ProxyNotAllowed { window } => {
+                map.serialize_entry("kind", "proxyNotAllowed")?;
+                map.serialize_entry("window", window)?;
+            }
+            FetchError::StreamNotFound(id) => {
+                map.serialize_entry("kind", "streamNotFound")?;
+                map.serialize_entry("stream", id)?;
+            }
+            FetchError::StreamNotAllowed { window, stream } => {
+                map.serialize_entry("kind", "streamNotAllowed")?;
+                map.serialize_entry("window", window)?;
+                map.serialize_entry("stream", stream)?;
+            }
+            FetchError::UploadNotFound(id) => {
+                map.serialize_entry("kind", "uploadNotFound")?;
+                map.serialize_entry("upload", id)?;
+            }
+            FetchError::UploadNotAllowed { window, upload } => {
+                map.serialize_entry("kind", "uploadNotAllowed")?;
+                map.serialize_entry("window", window)?;
+                map.serialize_entry("upload", upload)?;
+            }
+            FetchError::UploadClosed(id) => {
+                map.serialize_entry("kind", "uploadClosed")?;
+                map.serialize_entry("upload", id)?;
+            }
+            FetchError::Aborted => map.serialize_entry("kind", "aborted")?,
+            FetchError::AbortIdInUse(id) => {
+                map.serialize_entry("kind", "abortIdInUse")?;
+                map.serialize_entry("abort", id)?;
+            }
+            FetchError::ConnectTimeout => map.serialize_entry("kind", "connectTimeout")?,
+            FetchError::ReadTimeout => map.serialize_entry("kind", "readTimeout")?,
+            FetchError::Timeout => map.serialize_entry("kind", "timeout")?,
+            FetchError::Redirected { url, status } => {
+                map.serialize_entry("kind", "redirected")?;
+                map.serialize_entry("url", url)?;
+                map.serialize_entry("status", status)?;
+            }
+            FetchError::TooManyRedirects(url) => {
+                map.serialize_entry("kind", "tooManyRedirects")?;
+                map.serialize_entry("url", url)?;
+            }
+            FetchError::RedirectBodyNotReplayable(url) => {
+                map.serialize_entry("kind", "redirectBodyNotReplayable")?;
+                map.serialize_entry("url", url)?;
+            }
+            FetchError::CertificatePinMismatch(host) => {
+                map.serialize_entry("kind", "certificatePinMismatch")?;
+                map.serialize_entry("host", host)?;
+            }
+            FetchError::Decompress {
+                encoding,
+                reason: _,
+            } => {
+                map.serialize_entry("kind", "decompress")?;
+                map.serialize_entry("encoding", encoding)?;
+            }
+            FetchError::Decode {
+                url,
+                status,
+                response_type,
+                reason: _,
+            } => {
+                map.serialize_entry("kind", "decode")?;
+                map.serialize_entry("url", url)?;
+                map.serialize_entry("status", status)?;
+                map.serialize_entry("responseType", response_type.as_str())?;
+            }
+            FetchError::NotCached(url) => {
+                map.serialize_entry("kind", "notCached")?;
+                map.serialize_entry("url", url)?;
+            }
+        }
+        map.serialize_entry("message", &self.to_string())?;
+        map.end()
+    }
+}
+
+fn serialize_reqwest_error<M: SerializeMap>(
+    map: &mut M,
+    e: &reqwest::Error,
+) -> Result<(), M::Error> {
+    map.serialize_entry("kind", "request")?;
+    map.serialize_entry("class", ReqwestErrorClass::of(e).as_str())?;
+    if let Some(url) = e.url() {
+        map.serialize_entry("url", url.as_str())?;
+    }
+    if let Some(status) = e.status() {
+        map.serialize_entry("status", &status.as_u16())?;
+    }
+    Ok(())
+}
+
+/// `e`の原因を順に返す。`io::Error`の`source`は包んだエラー自体を飛ばすため、`get_ref`で辿る。
+pub(crate) fn causes(
+    e: &reqwest::Error,
+) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+    std::iter::successors(std::error::Error::source(e), |s| {
+        match s
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+        {
+            Some(inner) => Some(inner as &(dyn std::error::Error + 'static)),
+            None => s.source(),
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReqwestErrorClass {
+    Dns,
+    Tls,
+    Connect,
+    Timeout,
+    Decode,
+    Redirect,
+    Body,
+    Status,
+    Builder,
+    Request,
+    Other,
+}
+
+impl ReqwestErrorClass {
+    fn of(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ReqwestErrorClass::Timeout
+        } else if e.is_connect() {
+            Self::of_connect(e)
+        } else if e.is_decode() {
+            ReqwestErrorClass::Decode
+        } else if e.is_redirect() {
+            ReqwestErrorClass::Redirect
+        } else if e.is_body() {
+            ReqwestErrorClass::Body
+        } else if e.is_status() {
+            ReqwestErrorClass::Status
+        } else if e.is_builder() {
+            ReqwestErrorClass::Builder
+        } else if e.is_request() {
+            ReqwestErrorClass::Request
+        } else {
+            ReqwestErrorClass::Other
+        }
+    }
+
+    /// reqwestは名前解決とTLSの失敗を区別しないため、原因を辿って型から判断する。
+    fn of_connect(e: &reqwest::Error) -> Self {
+        for s in causes(e) {
+            if s.is::<crate::dns::ResolveError>() {
+                return ReqwestErrorClass::Dns;
+            }
+            if s.is::<native_tls::Error>() || s.is::<rustls::Error>() {
+                return ReqwestErrorClass::Tls;
+            }
+        }
+        ReqwestErrorClass::Connect
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ReqwestErrorClass::Dns => "dns",
+            ReqwestErrorClass::Tls => "tls",
+            ReqwestErrorClass::Connect => "connect",
+            ReqwestErrorClass::Timeout => "timeout",
+            ReqwestErrorClass::Decode => "decode",
+            ReqwestErrorClass::Redirect => "redirect",
+            ReqwestErrorClass::Body => "body",
+            ReqwestErrorClass::Status => "status",
+            ReqwestErrorClass::Builder => "builder",
+            ReqwestErrorClass::Request => "request",
+            ReqwestErrorClass::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct InvalidCookie {
+    pub domain: String,
+    pub name: String,
+    pub reason: CookieRejection,
+}
+
+impl std::fmt::Display for InvalidCookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` of domain `{}` ({})",
+            self.name, self.domain, self.reason
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieRejection {
+    /// キーのドメインからURLを組み立てられない。
+    InvalidDomain,
+    DomainMismatch,
+    PublicSuffix,
+    /// Secure属性を持つcookieを安全でないスキームで設定しようとした。
+    SecureOnInsecureScheme,
+    NonHttpScheme,
+    NonRelativeScheme,
+    Expired,
+    Parse,
+    UnspecifiedDomain,
+}
+
+impl From<&cookie_store::CookieError> for CookieRejection {
+    fn from(e: &cookie_store::CookieError) -> Self {
+        match e {
+            cookie_store::CookieError::NonHttpScheme => CookieRejection::NonHttpScheme,
+            cookie_store::CookieError::NonRelativeScheme => CookieRejection::NonRelativeScheme,
+            cookie_store::CookieError::DomainMismatch => CookieRejection::DomainMismatch,
+            cookie_store::CookieError::Expired => CookieRejection::Expired,
+            cookie_store::CookieError::Parse => CookieRejection::Parse,
+            cookie_store::CookieError::PublicSuffix => CookieRejection::PublicSuffix,
+            cookie_store::CookieError::UnspecifiedDomain => CookieRejection::UnspecifiedDomain,
+        }
+    }
+}
+
+impl std::fmt::Display for CookieRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CookieRejection::InvalidDomain => "invalid domain",
+            CookieRejection::DomainMismatch => "domain mismatch",
+            CookieRejection::PublicSuffix => "domain is a public suffix",
+            CookieRejection::SecureOnInsecureScheme => "secure cookie on insecure scheme",
+            CookieRejection::NonHttpScheme => "HttpOnly cookie on non-http scheme",
+            CookieRejection::NonRelativeScheme => "url has no host",
+            CookieRejection::Expired => "expired",
+            CookieRejection::Parse => "malformed cookie",
+            CookieRejection::UnspecifiedDomain => "domain is not specified",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serialize_with_kind() {
+        let e = FetchError::InvalidCookies(vec![InvalidCookie {
+            domain: "example.com".to_string(),
+            name: "token".to_string(),
+            reason: CookieRejection::SecureOnInsecureScheme,
+        }]);
+        let json = serde_json::to_value(&e).unwrap();
+
+        assert_eq!(json["kind"], "invalidCookies");
+        assert_eq!(json["cookies"][0]["domain"], "example.com");
+        assert_eq!(json["cookies"][0]["reason"], "secureOnInsecureScheme");
+        assert_eq!(json["message"], e.to_string());
+    }
+
+    #[test]
+    fn classify_connect_errors() {
+        let client = crate::cookie_client::CookieClient::new().unwrap();
+        // 接続を受け付けないポートを得るため、一度だけ待ち受けて閉じる。
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let classify = |url: String| {
+            let e = runtime
+                .block_on(client.request(reqwest::Method::GET, url).send())
+                .unwrap_err();
+            ReqwestErrorClass::of(&e)
+        };
+
+        assert_eq!(
+            classify("http://example.invalid/".to_string()),
+            ReqwestErrorClass::Dns
+        );
+        assert_eq!(
+            classify(format!("http://127.0.0.1:{}/", port)),
+            ReqwestErrorClass::Connect
+        );
+
+        // TLSを話さないサーバーへhttpsで接続する。
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            use std::io::Write;
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        });
+        assert_eq!(
+            classify(format!("https://127.0.0.1:{}/", port)),
+            ReqwestErrorClass::Tls
+        );
+        handle.join().unwrap();
+    }
+}
@@ -1,5 +1,8 @@
-use super::{cookie_props::CookieProps, headermap::HeaderMap, method::Method, redirect::Redirect};
-use std::collections::HashMap;
+use super::{
+    body::Body, headermap::HeaderMap, method::Method, millis_serde, redirect::Redirect, AbortId,
+    CacheMode, Cookies, ResponseCookies, ResponseType, Timeouts, UploadId,
+};
+use std::{collections::HashMap, time::Duration};
 
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -9,17 +12,101 @@ pub struct FetchOptions {
     #[serde(default = "HeaderMap::new")]
     pub headers: HeaderMap,
     #[serde(default = "HashMap::new")]
-    pub cookies: HashMap<String, HashMap<String, CookieProps>>,
-    #[serde(default = "default_redirect_policy")]
+    pub cookies: Cookies,
+    #[serde(default)]
     pub redirect: Redirect,
-    #[serde(default = "Vec::new")]
-    pub body: Vec<u8>,
+    #[serde(default)]
+    pub body: Body,
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub response_cookies: ResponseCookies,
+    #[serde(default)]
+    pub response_type: ResponseType,
+    /// `Content-Encoding`が`gzip`、`deflate`、`br`、`zstd`のボディを復号する。
+    #[serde(default = "default_decompress")]
+    pub decompress: bool,
+    /// GET以外のリクエストはキャッシュを用いない。
+    #[serde(default)]
+    pub cache: CacheMode,
+    /// ボディをバッファせず、ヘッダの受信後すぐに`Response.stream`を返す。`response_type`は無視される。
+    #[serde(default)]
+    pub stream: bool,
+    /// `body`の代わりに、`open_upload`で開いたアップロードをボディとして送る。
+    #[serde(default)]
+    pub upload: Option<UploadId>,
+    /// `abort`コマンドでこのリクエストを中断するためのトークン。
+    #[serde(default)]
+    pub abort: Option<AbortId>,
+    /// 制限時間はミリ秒で指定する。各項目の意味は`Timeouts`と同じ。
+    #[serde(default, with = "millis_serde")]
+    pub timeout: Option<Duration>,
+    #[serde(default, with = "millis_serde")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default, with = "millis_serde")]
+    pub read_timeout: Option<Duration>,
+    /// 辿ったリダイレクトを`Response.redirects`に記録する。
+    #[serde(default)]
+    pub record_redirects: bool,
 }
 
-fn default_redirect_policy() -> Redirect {
-    Redirect::Follow
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            method: default_method(),
+            headers: HeaderMap::new(),
+            cookies: HashMap::new(),
+            redirect: Redirect::default(),
+            body: Body::default(),
+            session: None,
+            response_cookies: ResponseCookies::default(),
+            response_type: ResponseType::default(),
+            decompress: default_decompress(),
+            cache: CacheMode::default(),
+            stream: false,
+            upload: None,
+            abort: None,
+            timeout: None,
+            connect_timeout: None,
+            read_timeout: None,
+            record_redirects: false,
+        }
+    }
+}
+
+impl FetchOptions {
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+        }
+    }
+}
+
+fn default_decompress() -> bool {
+    true
 }
 
 fn default_method() -> Method {
     Method::GET
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deserialize_binary_body_with_timeouts() {
+        let value = rmpv::Value::Map(vec![
+            ("body".into(), rmpv::Value::Binary(vec![1, 2, 3])),
+            ("connectTimeout".into(), 1500.into()),
+        ]);
+        let options: FetchOptions =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&value).unwrap()).unwrap();
+
+        assert!(matches!(options.body, Body::Bytes(ref b) if b == &[1, 2, 3]));
+        assert_eq!(options.connect_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(options.timeouts().timeout, None);
+    }
+}
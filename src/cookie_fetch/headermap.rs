@@ -8,7 +8,7 @@ use std::{
     str::FromStr,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HeaderMap(reqwest::header::HeaderMap);
 
 impl Deref for HeaderMap {
@@ -24,6 +24,12 @@ impl DerefMut for HeaderMap {
     }
 }
 
+impl Default for HeaderMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HeaderMap {
     pub fn new() -> Self {
         Self(reqwest::header::HeaderMap::new())
@@ -34,9 +40,9 @@ impl HeaderMap {
     }
 }
 
-impl Into<reqwest::header::HeaderMap> for HeaderMap {
-    fn into(self) -> reqwest::header::HeaderMap {
-        self.0
+impl From<HeaderMap> for reqwest::header::HeaderMap {
+    fn from(value: HeaderMap) -> Self {
+        value.0
     }
 }
 
@@ -0,0 +1,315 @@
+use super::{CookieChange, CookieProps, CookieRejection, Cookies, FetchError, InvalidCookie};
+use crate::CookieClient;
+use reqwest_cookie_store::CookieStore;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieFilter {
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl CookieFilter {
+    fn matches(&self, cookie: &cookie_store::Cookie<'_>) -> bool {
+        let domain = cookie.domain.as_cow();
+        self.domain
+            .as_deref()
+            .is_none_or(|d| domain.as_deref() == Some(d))
+            && self
+                .path
+                .as_deref()
+                .is_none_or(|p| String::from(&cookie.path) == p)
+            && self.name.as_deref().is_none_or(|n| cookie.name() == n)
+    }
+}
+
+pub fn collect_cookies<'a>(
+    iter: impl Iterator<Item = &'a cookie_store::Cookie<'static>>,
+) -> Cookies {
+    let mut cookies: Cookies = HashMap::new();
+
+    for c in iter {
+        insert_cookie(&mut cookies, c, None);
+    }
+
+    cookies
+}
+
+fn insert_cookie(
+    cookies: &mut Cookies,
+    cookie: &cookie_store::Cookie<'_>,
+    change: Option<CookieChange>,
+) {
+    let Some(domain) = &cookie.domain.as_cow() else {
+        return;
+    };
+
+    let pairs: &mut HashMap<_, _> = { cookies.entry(domain.to_string()).or_default() };
+    let mut props = CookieProps::from(&**cookie);
+    props.change = change;
+    pairs.insert(cookie.name().to_string(), props);
+}
+
+type CookieKey = (String, String, String);
+
+fn cookie_key(cookie: &cookie_store::Cookie<'_>) -> CookieKey {
+    (
+        String::from(&cookie.domain),
+        String::from(&cookie.path),
+        cookie.name().to_string(),
+    )
+}
+
+/// リクエストの前後でjarを比較し、追加・変更・削除されたcookieを求めるためのスナップショット。
+pub struct CookieSnapshot(HashMap<CookieKey, cookie_store::Cookie<'static>>);
+
+impl CookieSnapshot {
+    pub fn take(store: &CookieStore) -> Self {
+        Self(
+            store
+                .iter_unexpired()
+                .map(|c| (cookie_key(c), c.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn diff(&self, store: &CookieStore) -> Cookies {
+        let mut cookies: Cookies = HashMap::new();
+        let mut seen = HashSet::new();
+
+        for c in store.iter_unexpired() {
+            let key = cookie_key(c);
+            let change = match self.0.get(&key) {
+                None => Some(CookieChange::Added),
+                Some(old) if old != c => Some(CookieChange::Changed),
+                Some(_) => None,
+            };
+            seen.insert(key);
+
+            if let Some(change) = change {
+                insert_cookie(&mut cookies, c, Some(change));
+            }
+        }
+
+        for (key, c) in &self.0 {
+            if !seen.contains(key) {
+                insert_cookie(&mut cookies, c, Some(CookieChange::Removed));
+            }
+        }
+
+        cookies
+    }
+}
+
+pub fn list_cookies(client: &CookieClient, filter: &CookieFilter) -> Cookies {
+    let store = client.cookie_store();
+    collect_cookies(store.iter_unexpired().filter(|c| filter.matches(c)))
+}
+
+pub fn get_cookie(
+    client: &CookieClient,
+    domain: &str,
+    path: &str,
+    name: &str,
+) -> Option<CookieProps> {
+    let store = client.cookie_store();
+    store
+        .get(domain, path, name)
+        .map(|c| CookieProps::from(&**c))
+}
+
+/// `FetchOptions.cookies`と同じ形式でcookieを設定する。キーのドメインに対するhttpsのリクエストから受け取ったものとして扱う。
+pub fn set_cookies(client: &CookieClient, cookies: Cookies) -> Result<(), FetchError> {
+    let mut store = client.cookie_store();
+    insert_cookies(&mut store, cookies, "https")
+}
+
+/// 各cookieを、マップのキーのドメインとcookieのパスから組み立てたURLへのレスポンスで受け取ったものとして挿入する。
+///
+/// 拒否されたcookieは全て理由とともに報告する。拒否されなかったcookieは挿入されたままになる。
+pub fn insert_cookies(
+    store: &mut CookieStore,
+    cookies: Cookies,
+    scheme: &str,
+) -> Result<(), FetchError> {
+    let mut rejected = Vec::new();
+
+    for (domain, pairs) in cookies {
+        for (name, props) in pairs {
+            let result = cookie_url(scheme, &domain, props.path.as_deref()).and_then(|url| {
+                let cookie = props.into_raw_cookie(name.clone());
+                if cookie.secure() == Some(true) && url.scheme() != "https" {
+                    return Err(CookieRejection::SecureOnInsecureScheme);
+                }
+
+                store
+                    .insert_raw(&cookie, &url)
+                    .map(|_| ())
+                    .map_err(|e| CookieRejection::from(&e))
+            });
+
+            if let Err(reason) = result {
+                rejected.push(InvalidCookie {
+                    domain: domain.clone(),
+                    name,
+                    reason,
+                });
+            }
+        }
+    }
+
+    if rejected.is_empty() {
+        Ok(())
+    } else {
+        Err(FetchError::InvalidCookies(rejected))
+    }
+}
+
+fn cookie_url(
+    scheme: &str,
+    domain: &str,
+    path: Option<&str>,
+) -> Result<reqwest::Url, CookieRejection> {
+    let host = domain.strip_prefix('.').unwrap_or(domain);
+    let mut url = reqwest::Url::parse(&format!("{}://{}/", scheme, host))
+        .map_err(|_| CookieRejection::InvalidDomain)?;
+
+    if url.host_str() != Some(host) {
+        return Err(CookieRejection::InvalidDomain);
+    }
+
+    if let Some(path) = path {
+        url.set_path(path);
+    }
+
+    Ok(url)
+}
+
+pub fn delete_cookie(client: &CookieClient, domain: &str, path: &str, name: &str) -> bool {
+    let mut store = client.cookie_store();
+    store.remove(domain, path, name).is_some()
+}
+
+pub fn clear_cookies(client: &CookieClient) {
+    let mut store = client.cookie_store();
+    store.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cookies(domain: &str, name: &str, props: &str) -> Cookies {
+        let props: CookieProps = serde_json::from_str(props).unwrap();
+        HashMap::from([(
+            domain.to_string(),
+            HashMap::from([(name.to_string(), props)]),
+        )])
+    }
+
+    #[test]
+    fn manage_session_cookies() {
+        let client = CookieClient::new().unwrap();
+        set_cookies(
+            &client,
+            cookies("example.com", "a", r#"{"value":"1","path":"/x"}"#),
+        )
+        .unwrap();
+        set_cookies(&client, cookies("example.org", "b", r#"{"value":"2"}"#)).unwrap();
+
+        let all = list_cookies(&client, &CookieFilter::default());
+        assert_eq!(all.len(), 2);
+        let filter = CookieFilter {
+            domain: Some("example.com".into()),
+            ..Default::default()
+        };
+        let listed = list_cookies(&client, &filter);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed["example.com"]["a"].value, "1");
+
+        let cookie = get_cookie(&client, "example.com", "/x", "a").unwrap();
+        assert_eq!(cookie.value, "1");
+        assert!(get_cookie(&client, "example.com", "/", "a").is_none());
+
+        assert!(delete_cookie(&client, "example.com", "/x", "a"));
+        assert!(!delete_cookie(&client, "example.com", "/x", "a"));
+        assert!(get_cookie(&client, "example.com", "/x", "a").is_none());
+
+        clear_cookies(&client);
+        assert!(list_cookies(&client, &CookieFilter::default()).is_empty());
+    }
+
+    #[test]
+    fn diff_snapshot() {
+        let url = reqwest::Url::parse("https://example.com/").unwrap();
+        let mut store = CookieStore::new(None);
+        for cookie in ["keep=1", "old=1", "gone=1"] {
+            store.parse(cookie, &url).unwrap();
+        }
+
+        let snapshot = CookieSnapshot::take(&store);
+        for cookie in ["new=1", "old=2", "gone=; Max-Age=0"] {
+            store.parse(cookie, &url).unwrap();
+        }
+        let changes = &snapshot.diff(&store)["example.com"];
+
+        assert_eq!(changes.len(), 3);
+        assert!(matches!(changes["new"].change, Some(CookieChange::Added)));
+        assert!(matches!(changes["old"].change, Some(CookieChange::Changed)));
+        assert_eq!(changes["old"].value, "2");
+        assert!(matches!(
+            changes["gone"].change,
+            Some(CookieChange::Removed)
+        ));
+    }
+
+    #[test]
+    fn insert_against_key_domain() {
+        let mut store = CookieStore::new(None);
+        insert_cookies(
+            &mut store,
+            cookies(
+                "auth.example.com",
+                "token",
+                r#"{"value":"v","path":"/api"}"#,
+            ),
+            "https",
+        )
+        .unwrap();
+
+        assert!(store.contains("auth.example.com", "/api", "token"));
+    }
+
+    #[test]
+    fn report_rejected_cookies() {
+        let mut store = CookieStore::new(None);
+        let result = insert_cookies(
+            &mut store,
+            cookies(
+                "auth.example.com",
+                "token",
+                r#"{"value":"v","domain":"other.example.org"}"#,
+            ),
+            "https",
+        );
+        let Err(FetchError::InvalidCookies(rejected)) = result else {
+            panic!("cookie must be rejected");
+        };
+        assert_eq!(rejected[0].reason, CookieRejection::DomainMismatch);
+
+        let result = insert_cookies(
+            &mut store,
+            cookies("example.com", "token", r#"{"value":"v","secure":true}"#),
+            "http",
+        );
+        let Err(FetchError::InvalidCookies(rejected)) = result else {
+            panic!("cookie must be rejected");
+        };
+        assert_eq!(rejected[0].reason, CookieRejection::SecureOnInsecureScheme);
+    }
+}
@@ -23,9 +23,15 @@ impl Method {
     }
 }
 
-impl Into<reqwest::Method> for Method {
-    fn into(self) -> reqwest::Method {
-        self.0
+impl From<reqwest::Method> for Method {
+    fn from(value: reqwest::Method) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Method> for reqwest::Method {
+    fn from(value: Method) -> Self {
+        value.0
     }
 }
 
@@ -1,17 +1,46 @@
+mod abort;
+mod body;
+mod body_stream;
+mod cache_mode;
 mod cookie_props;
+mod decompress;
 mod fetch;
 mod fetch_error;
 mod fetch_options;
 mod headermap;
+mod jar;
 mod method;
 mod redirect;
+mod redirect_chain;
+mod request_builder;
 mod response;
+mod response_cookies;
+mod response_type;
+mod timeouts;
+mod upload_stream;
 
-use cookie_props::CookieProps;
-use headermap::HeaderMap;
-use redirect::Redirect;
+use std::collections::HashMap;
+
+pub use abort::{AbortId, Aborts};
+pub use body::{Body, Part, PartContent};
+pub use body_stream::{BodyStreams, StreamId};
+pub use cache_mode::CacheMode;
+pub use cookie_props::{CookieChange, CookieProps};
 
 pub use fetch::fetch;
-pub use fetch_error::FetchError;
+pub(crate) use fetch_error::causes;
+pub use fetch_error::{CookieRejection, FetchError, InvalidCookie};
 pub use fetch_options::FetchOptions;
-pub use response::Response;
+pub use headermap::HeaderMap;
+pub use jar::{clear_cookies, delete_cookie, get_cookie, list_cookies, set_cookies, CookieFilter};
+pub use redirect::{Redirect, RedirectTarget};
+pub use redirect_chain::RedirectHop;
+pub use request_builder::{CookieFetch, RequestBuilder};
+pub use response::{Response, ResponseBody};
+pub use response_cookies::ResponseCookies;
+pub use response_type::ResponseType;
+pub(crate) use timeouts::millis_serde;
+pub use timeouts::{with_read_timeout, Timeouts};
+pub use upload_stream::{UploadId, UploadStreams};
+
+pub type Cookies = HashMap<String, HashMap<String, CookieProps>>;
@@ -1,8 +1,51 @@
+use crate::RedirectPolicy;
+
+/// 辿るリダイレクトの上限。fetchの仕様に合わせる。`limit`にこれを超える値は指定できない。
+pub const MAX_REDIRECTS: usize = 20;
+
 #[derive(Debug)]
 pub enum Redirect {
-    Follow,
+    /// `limit`が`None`の場合は`MAX_REDIRECTS`まで辿る。
+    Follow {
+        limit: Option<usize>,
+        only: RedirectTarget,
+    },
+    /// リダイレクトに従わず、3xxのレスポンスをそのまま返す。
     Manual,
-    Limit { limit: usize },
+    /// リダイレクトを受け取った時点で`FetchError::Redirected`で失敗する。
+    Error,
+}
+
+/// 辿ってよいリダイレクト先。条件を満たさない場合は、そのリダイレクトのレスポンスを返す。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RedirectTarget {
+    #[default]
+    Any,
+    SameOrigin,
+    /// 設定されたスコープで許可されたURLのみ。スコープ外でも`NotAllowed`で失敗せず、リダイレクトのレスポンスを返す。
+    Scope,
+}
+
+impl Default for Redirect {
+    fn default() -> Self {
+        Redirect::Follow {
+            limit: None,
+            only: RedirectTarget::Any,
+        }
+    }
+}
+
+impl From<&Redirect> for RedirectPolicy {
+    fn from(value: &Redirect) -> Self {
+        match value {
+            Redirect::Follow { limit: None, .. } => RedirectPolicy::follow(),
+            Redirect::Follow {
+                limit: Some(limit), ..
+            } => RedirectPolicy::limited(*limit),
+            Redirect::Manual | Redirect::Error => RedirectPolicy::limited(0),
+        }
+    }
 }
 
 impl<'de> serde::Deserialize<'de> for Redirect {
@@ -10,18 +53,21 @@ impl<'de> serde::Deserialize<'de> for Redirect {
     where
         D: serde::Deserializer<'de>,
     {
-        struct KeyLimit;
-        impl<'de> serde::de::Deserialize<'de> for KeyLimit {
+        enum Key {
+            Limit,
+            Only,
+        }
+        impl<'de> serde::de::Deserialize<'de> for Key {
             fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
             where
                 D: serde::Deserializer<'de>,
             {
                 struct V;
                 impl<'de> serde::de::Visitor<'de> for V {
-                    type Value = KeyLimit;
+                    type Value = Key;
 
                     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                        formatter.write_str("`limit`")
+                        formatter.write_str("`limit` or `only`")
                     }
 
                     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -29,7 +75,8 @@ impl<'de> serde::Deserialize<'de> for Redirect {
                         E: serde::de::Error,
                     {
                         match v {
-                            "limit" => Ok(KeyLimit),
+                            "limit" => Ok(Key::Limit),
+                            "only" => Ok(Key::Only),
                             _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
                         }
                     }
@@ -44,32 +91,100 @@ impl<'de> serde::Deserialize<'de> for Redirect {
             type Value = Redirect;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("`follow`, `manual`, or `{ limit: number }`")
+                formatter.write_str(
+                    "`follow`, `manual`, `error`, `sameOrigin`, `scope`, or `{ limit?: number, only?: string }`",
+                )
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: serde::de::Error,
             {
-                match v {
-                    "follow" => Ok(Redirect::Follow),
-                    "manual" => Ok(Redirect::Manual),
-                    _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
-                }
+                let only = match v {
+                    "follow" => RedirectTarget::Any,
+                    "sameOrigin" => RedirectTarget::SameOrigin,
+                    "scope" => RedirectTarget::Scope,
+                    "manual" => return Ok(Redirect::Manual),
+                    "error" => return Ok(Redirect::Error),
+                    _ => return Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                };
+
+                Ok(Redirect::Follow { limit: None, only })
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
             where
                 A: serde::de::MapAccess<'de>,
             {
-                let Some((_, limit)) = map.next_entry::<KeyLimit, usize>()? else {
-                    return Err(<A::Error as serde::de::Error>::missing_field("limit"));
-                };
+                let mut limit = None;
+                let mut only = None;
+                while let Some(key) = map.next_key::<Key>()? {
+                    match key {
+                        Key::Limit => {
+                            if limit.is_some() {
+                                return Err(<A::Error as serde::de::Error>::duplicate_field(
+                                    "limit",
+                                ));
+                            }
+                            let value = map.next_value::<usize>()?;
+                            if value > MAX_REDIRECTS {
+                                return Err(<A::Error as serde::de::Error>::invalid_value(
+                                    serde::de::Unexpected::Unsigned(value as u64),
+                                    &"a limit of at most 20",
+                                ));
+                            }
+                            limit = Some(value);
+                        }
+                        Key::Only => {
+                            if only.is_some() {
+                                return Err(<A::Error as serde::de::Error>::duplicate_field(
+                                    "only",
+                                ));
+                            }
+                            only = Some(map.next_value::<RedirectTarget>()?);
+                        }
+                    }
+                }
 
-                Ok(Redirect::Limit { limit })
+                Ok(Redirect::Follow {
+                    limit,
+                    only: only.unwrap_or_default(),
+                })
             }
         }
 
         deserializer.deserialize_any(V)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deserialize_redirect() {
+        let redirect: Redirect = serde_json::from_str(r#""error""#).unwrap();
+        assert!(matches!(redirect, Redirect::Error));
+
+        let redirect: Redirect = serde_json::from_str(r#"{"limit":3}"#).unwrap();
+        assert!(matches!(
+            redirect,
+            Redirect::Follow {
+                limit: Some(3),
+                only: RedirectTarget::Any
+            }
+        ));
+
+        let redirect: Redirect = serde_json::from_str(r#"{"only":"sameOrigin"}"#).unwrap();
+        assert!(matches!(
+            redirect,
+            Redirect::Follow {
+                limit: None,
+                only: RedirectTarget::SameOrigin
+            }
+        ));
+
+        assert!(serde_json::from_str::<Redirect>(r#"{"limit":20}"#).is_ok());
+        assert!(serde_json::from_str::<Redirect>(r#"{"limit":50}"#).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use super::{
+    decompress::ACCEPT_ENCODING,
+    jar,
+    redirect::{Redirect, RedirectTarget, MAX_REDIRECTS},
+    with_read_timeout, Cookies, FetchError, Timeouts,
+};
+use crate::{scope::SharedScope, CookieClient, RedirectPolicy};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Method, StatusCode,
+};
+use std::time::Instant;
+
+/// リダイレクトのレスポンス一つ分の記録。
+#[serde_with::skip_serializing_none]
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+    pub location: Option<String>,
+    pub set_cookies: Vec<String>,
+    /// このホップの`Set-Cookie`によってjarに加えられた変更。
+    pub cookies: Cookies,
+}
+
+/// `request`を送り、`redirect`に従ってリダイレクトを辿る。
+///
+/// `record`が真の場合は辿ったホップを順に返す。`decompress`が真の場合は`Accept-Encoding`を補う。
+/// `conditional`はキャッシュの検証のためのヘッダで、最初のリクエストにのみ加える。
+pub async fn send(
+    client: &CookieClient,
+    http: &reqwest::Client,
+    mut request: reqwest::Request,
+    follow: Follow<'_>,
+    timeouts: &Timeouts,
+    decompress: bool,
+    mut conditional: HeaderMap,
+) -> Result<(reqwest::Response, Option<Vec<RedirectHop>>), FetchError> {
+    let deadline = timeouts.timeout.map(|t| Instant::now() + t);
+    let mut hops = follow.record.then(Vec::new);
+    let mut policy = RedirectPolicy::from(follow.redirect);
+    let mut count = 0;
+
+    loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(FetchError::Timeout);
+            }
+            *request.timeout_mut() = Some(remaining);
+        }
+
+        let replay = Replay::of(&request);
+        let snapshot = hops
+            .is_some()
+            .then(|| jar::CookieSnapshot::take(&client.cookie_store()));
+
+        // 利用者が指定したヘッダではないため、次のホップのスコープの確認に含めないよう複製の後に加える。
+        if decompress {
+            request
+                .headers_mut()
+                .entry(header::ACCEPT_ENCODING)
+                .or_insert(HeaderValue::from_static(ACCEPT_ENCODING));
+        }
+        request
+            .headers_mut()
+            .extend(std::mem::take(&mut conditional));
+
+        client.config().tls.check_plaintext(request.url())?;
+        let res = with_read_timeout(timeouts.read_timeout, http.execute(request)).await?;
+
+        let status = res.status();
+        if !is_redirect_status(status) {
+            return Ok((res, hops));
+        }
+        if let Redirect::Error = follow.redirect {
+            return Err(FetchError::Redirected {
+                url: res.url().to_string(),
+                status: status.as_u16(),
+            });
+        }
+
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let Some(target) = location
+            .as_deref()
+            .and_then(|l| res.url().join(l).ok())
+            .filter(|u| matches!(u.scheme(), "http" | "https"))
+        else {
+            return Ok((res, hops));
+        };
+
+        let (next, complete) = redirect(replay, status, target);
+        if !follow.allows(res.url(), &next) || !policy.check() {
+            return Ok((res, hops));
+        }
+
+        count += 1;
+        if count > MAX_REDIRECTS {
+            return Err(FetchError::TooManyRedirects(res.url().to_string()));
+        }
+        if !complete {
+            return Err(FetchError::RedirectBodyNotReplayable(res.url().to_string()));
+        }
+        // 許可されたホストから任意のホストへcookieごと転送されないよう、各ホップでスコープを確認する。
+        if !follow.scope.allows_except(&next, follow.implied) {
+            return Err(FetchError::NotAllowed(next.url().clone()));
+        }
+
+        if let (Some(hops), Some(snapshot)) = (&mut hops, snapshot) {
+            let set_cookies = res
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+                .collect();
+
+            hops.push(RedirectHop {
+                url: res.url().to_string(),
+                status: status.as_u16(),
+                location,
+                set_cookies,
+                cookies: snapshot.diff(&client.cookie_store()),
+            });
+        }
+
+        request = next;
+    }
+}
+
+/// リダイレクトの辿り方。
+pub struct Follow<'a> {
+    pub redirect: &'a Redirect,
+    pub scope: &'a SharedScope,
+    pub record: bool,
+    /// ボディから付けられたヘッダ。スコープの`headers`と照合しない。
+    pub implied: &'a [header::HeaderName],
+}
+
+impl Follow<'_> {
+    fn allows(&self, from: &reqwest::Url, to: &reqwest::Request) -> bool {
+        let Redirect::Follow { only, .. } = self.redirect else {
+            return false;
+        };
+
+        match only {
+            RedirectTarget::Any => true,
+            RedirectTarget::SameOrigin => from.origin() == to.url().origin(),
+            RedirectTarget::Scope => self.scope.allows_except(to, self.implied),
+        }
+    }
+}
+
+fn is_redirect_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// 送信前に複製しておいたリクエスト。ストリームのボディは複製できないため、その場合はボディを除いて保持する。
+enum Replay {
+    Full(reqwest::Request),
+    WithoutBody(reqwest::Request),
+}
+
+impl Replay {
+    fn of(request: &reqwest::Request) -> Self {
+        if let Some(request) = request.try_clone() {
+            return Replay::Full(request);
+        }
+
+        let mut copy = reqwest::Request::new(request.method().clone(), request.url().clone());
+        *copy.headers_mut() = request.headers().clone();
+        *copy.timeout_mut() = request.timeout().copied();
+        *copy.version_mut() = request.version();
+        Replay::WithoutBody(copy)
+    }
+}
+
+/// リダイレクト先へのリクエストを作る。
+///
+/// メソッドとボディはfetchの仕様に従って書き換える。ボディを送り直す必要があるのに複製できなかった場合は、ボディを欠いたリクエストと`false`を返す。
+fn redirect(replay: Replay, status: StatusCode, target: reqwest::Url) -> (reqwest::Request, bool) {
+    let (mut request, has_body) = match replay {
+        Replay::Full(request) => (request, true),
+        Replay::WithoutBody(request) => (request, false),
+    };
+
+    let method = request.method();
+    let to_get = match status {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => method == Method::POST,
+        StatusCode::SEE_OTHER => method != Method::GET && method != Method::HEAD,
+        _ => false,
+    };
+
+    if to_get {
+        *request.method_mut() = Method::GET;
+        *request.body_mut() = None;
+        remove_body_headers(request.headers_mut());
+    }
+
+    // スキームが変わる場合も、httpsで送った認証情報を平文で送らないよう取り除く。
+    if request.url().origin() != target.origin() {
+        let headers = request.headers_mut();
+        headers.remove(header::AUTHORIZATION);
+        headers.remove(header::COOKIE);
+        headers.remove(header::PROXY_AUTHORIZATION);
+        headers.remove(header::WWW_AUTHENTICATE);
+    }
+
+    *request.url_mut() = target;
+    (request, to_get || has_body)
+}
+
+/// fetchの仕様の"request-body-header name"と`Content-Length`。
+fn remove_body_headers(headers: &mut HeaderMap) {
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LANGUAGE);
+    headers.remove(header::CONTENT_LOCATION);
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scope::Rule;
+
+    fn replay(method: Method, url: &str) -> Replay {
+        let mut request = reqwest::Request::new(method, url.parse().unwrap());
+        *request.body_mut() = Some(b"body".to_vec().into());
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "token".parse().unwrap());
+        Replay::Full(request)
+    }
+
+    fn url(url: &str) -> reqwest::Url {
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn rewrite_method_by_status() {
+        let request = redirect(
+            replay(Method::POST, "https://example.com/a"),
+            StatusCode::SEE_OTHER,
+            url("https://example.com/b"),
+        )
+        .0;
+        assert_eq!(request.method(), Method::GET);
+        assert!(request.body().is_none());
+        assert_eq!(request.url().as_str(), "https://example.com/b");
+
+        let request = redirect(
+            replay(Method::PUT, "https://example.com/a"),
+            StatusCode::MOVED_PERMANENTLY,
+            url("https://example.com/b"),
+        )
+        .0;
+        assert_eq!(request.method(), Method::PUT);
+        assert!(request.body().is_some());
+
+        let request = redirect(
+            replay(Method::POST, "https://example.com/a"),
+            StatusCode::TEMPORARY_REDIRECT,
+            url("https://example.com/b"),
+        )
+        .0;
+        assert_eq!(request.method(), Method::POST);
+        assert!(request.body().is_some());
+        assert!(request.headers().contains_key(header::AUTHORIZATION));
+    }
+
+    #[test]
+    fn strip_credentials_across_hosts() {
+        let request = redirect(
+            replay(Method::POST, "https://example.com/a"),
+            StatusCode::PERMANENT_REDIRECT,
+            url("https://other.example.org/"),
+        )
+        .0;
+        assert!(!request.headers().contains_key(header::AUTHORIZATION));
+
+        let request = redirect(
+            replay(Method::GET, "https://example.com:8443/a"),
+            StatusCode::FOUND,
+            url("http://example.com:8443/b"),
+        )
+        .0;
+        assert!(!request.headers().contains_key(header::AUTHORIZATION));
+    }
+
+    #[test]
+    fn unreplayable_body() {
+        let replay = Replay::WithoutBody(reqwest::Request::new(
+            Method::POST,
+            url("https://example.com/a"),
+        ));
+        let (_, complete) = redirect(
+            replay,
+            StatusCode::TEMPORARY_REDIRECT,
+            url("https://example.com/b"),
+        );
+        assert!(!complete);
+    }
+
+    #[test]
+    fn record_each_hop() {
+        use super::super::CookieChange;
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            for response in [
+                "HTTP/1.1 302 Found\r\nset-cookie: id=1\r\nlocation: /b\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                "HTTP/1.1 301 Moved Permanently\r\nset-cookie: id=2\r\nlocation: /c\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    head.extend_from_slice(&buf[..n]);
+                }
+                write!(stream, "{}", response).unwrap();
+            }
+        });
+
+        let client = CookieClient::new().unwrap();
+        let http = client.http_client(None).unwrap();
+        let base = format!("http://127.0.0.1:{}", port);
+        let request = http
+            .request(Method::GET, format!("{}/a", base))
+            .build()
+            .unwrap();
+
+        let scope = SharedScope::default();
+        scope.add_allowed(Rule::Glob(
+            glob::Pattern::new(&format!("{}/*", base)).unwrap(),
+        ));
+        let follow = Follow {
+            redirect: &Redirect::default(),
+            scope: &scope,
+            record: true,
+            implied: &[],
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (res, hops) = runtime
+            .block_on(send(
+                &client,
+                &http,
+                request,
+                follow,
+                &Timeouts::default(),
+                false,
+                HeaderMap::new(),
+            ))
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(res.url().as_str(), format!("{}/c", base));
+        let hops = hops.unwrap();
+        assert_eq!(hops.len(), 2);
+
+        assert_eq!(hops[0].url, format!("{}/a", base));
+        assert_eq!(hops[0].status, 302);
+        assert_eq!(hops[0].location.as_deref(), Some("/b"));
+        assert_eq!(hops[0].set_cookies, ["id=1"]);
+        let id = &hops[0].cookies["127.0.0.1"]["id"];
+        assert!(matches!(id.change, Some(CookieChange::Added)));
+
+        assert_eq!(hops[1].url, format!("{}/b", base));
+        assert_eq!(hops[1].status, 301);
+        let id = &hops[1].cookies["127.0.0.1"]["id"];
+        assert!(matches!(id.change, Some(CookieChange::Changed)));
+        assert_eq!(id.value, "2");
+    }
+
+    #[test]
+    fn reject_redirect_out_of_scope() {
+        use std::io::{Read, Write};
+
+        let allowed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let blocked = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        blocked.set_nonblocking(true).unwrap();
+        let allowed_port = allowed.local_addr().unwrap().port();
+        let target = format!(
+            "http://127.0.0.1:{}/secret",
+            blocked.local_addr().unwrap().port()
+        );
+
+        let location = target.clone();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = allowed.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                head.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 302 Found\r\nlocation: {}\r\ncontent-length: 0\r\n\r\n",
+                location
+            )
+            .unwrap();
+        });
+
+        let scope = SharedScope::default();
+        scope.add_allowed(Rule::Glob(
+            glob::Pattern::new(&format!("http://127.0.0.1:{}/*", allowed_port)).unwrap(),
+        ));
+        let client = CookieClient::new().unwrap();
+        let http = client.http_client(None).unwrap();
+        let request = client
+            .request(Method::GET, format!("http://127.0.0.1:{}/", allowed_port))
+            .build()
+            .unwrap();
+        let follow = Follow {
+            redirect: &Redirect::default(),
+            scope: &scope,
+            record: false,
+            implied: &[],
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(send(
+            &client,
+            &http,
+            request,
+            follow,
+            &Timeouts::default(),
+            false,
+            HeaderMap::new(),
+        ));
+        handle.join().unwrap();
+
+        match result {
+            Err(FetchError::NotAllowed(url)) => assert_eq!(url.as_str(), target),
+            other => panic!(
+                "unexpected result: {:?}",
+                other.map(|(res, _)| res.status())
+            ),
+        }
+        assert_eq!(
+            blocked.accept().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+}
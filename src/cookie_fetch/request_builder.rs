@@ -0,0 +1,236 @@
+use super::{
+    fetch, AbortId, Body, CacheMode, Cookies, FetchError, FetchOptions, Part, Redirect, Response,
+    ResponseCookies, ResponseType,
+};
+use crate::CookieFetchState;
+use reqwest::header::{HeaderName, HeaderValue};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime, State};
+
+/// ホスト側からリクエストを送る。
+///
+/// webviewからの`fetch`とセッション、スコープ、リダイレクトの扱いを共有する。ウィンドウごとの制限は受けない。
+pub struct CookieFetch<R: Runtime> {
+    app: AppHandle<R>,
+}
+
+impl<R: Runtime> CookieFetch<R> {
+    pub(crate) fn new(app: AppHandle<R>) -> Self {
+        Self { app }
+    }
+
+    pub fn get(&self, url: impl Into<String>) -> RequestBuilder<R> {
+        self.request(reqwest::Method::GET, url)
+    }
+
+    pub fn post(&self, url: impl Into<String>) -> RequestBuilder<R> {
+        self.request(reqwest::Method::POST, url)
+    }
+
+    pub fn request(&self, method: reqwest::Method, url: impl Into<String>) -> RequestBuilder<R> {
+        RequestBuilder {
+            app: self.app.clone(),
+            url: url.into(),
+            options: FetchOptions {
+                method: method.into(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+pub struct RequestBuilder<R: Runtime> {
+    app: AppHandle<R>,
+    url: String,
+    options: FetchOptions,
+}
+
+impl<R: Runtime> RequestBuilder<R> {
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.options.headers.append(name, value);
+        self
+    }
+
+    pub fn headers(mut self, headers: reqwest::header::HeaderMap) -> Self {
+        self.options.headers.extend(headers);
+        self
+    }
+
+    /// 送信前にjarへ加えるcookie。
+    pub fn cookies(mut self, cookies: Cookies) -> Self {
+        self.options.cookies = cookies;
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.options.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// `application/json`として送る。
+    pub fn json(mut self, value: serde_json::Value) -> Self {
+        self.options.body = Body::Json(value);
+        self
+    }
+
+    /// `application/x-www-form-urlencoded`として送る。
+    pub fn form(mut self, fields: Vec<(String, String)>) -> Self {
+        self.options.body = Body::Form(fields);
+        self
+    }
+
+    /// `multipart/form-data`として送る。
+    pub fn multipart(mut self, parts: Vec<Part>) -> Self {
+        self.options.body = Body::Multipart(parts);
+        self
+    }
+
+    /// 既定のjarの代わりに、開かれたセッションのjarを用いる。
+    pub fn session(mut self, id: impl Into<String>) -> Self {
+        self.options.session = Some(id.into());
+        self
+    }
+
+    pub fn response_type(mut self, response_type: ResponseType) -> Self {
+        self.options.response_type = response_type;
+        self
+    }
+
+    /// `Content-Encoding`を復号するか。既定では復号する。
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.options.decompress = decompress;
+        self
+    }
+
+    /// GET以外のリクエストでは無視される。
+    pub fn cache(mut self, cache: CacheMode) -> Self {
+        self.options.cache = cache;
+        self
+    }
+
+    pub fn redirect(mut self, redirect: Redirect) -> Self {
+        self.options.redirect = redirect;
+        self
+    }
+
+    pub fn response_cookies(mut self, response_cookies: ResponseCookies) -> Self {
+        self.options.response_cookies = response_cookies;
+        self
+    }
+
+    /// `abort`コマンド、あるいはwebview側の`AbortSignal`と同じ方法で中断するためのトークン。
+    pub fn abort(mut self, id: AbortId) -> Self {
+        self.options.abort = Some(id);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn record_redirects(mut self, record: bool) -> Self {
+        self.options.record_redirects = record;
+        self
+    }
+
+    pub async fn send(self) -> Result<Response, FetchError> {
+        let state: State<'_, CookieFetchState> = self.app.state();
+        let policy = state.config.host_policy();
+        fetch(&state, policy, self.url, Some(self.options)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cookie_fetch::CookieChange;
+
+    #[test]
+    fn send_options_from_host() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                head.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nset-cookie: b=2\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok"
+            )
+            .unwrap();
+            String::from_utf8(head).unwrap().to_ascii_lowercase()
+        });
+
+        // ウィンドウごとの制限は、ホスト側からのリクエストには適用されない。
+        let config = serde_json::from_str(&format!(
+            r#"{{"scope": {{"allowlist": [{{"port": {}}}]}}, "windows": {{"main": {{"sessions": []}}}}}}"#,
+            port
+        ))
+        .unwrap();
+        let app = tauri::test::mock_app();
+        app.manage(CookieFetchState {
+            client_pool: crate::CookieClientPool::new(),
+            sessions: crate::session::Sessions::new(None, Default::default()),
+            body_streams: super::super::BodyStreams::new(),
+            upload_streams: super::super::UploadStreams::new(),
+            aborts: super::super::Aborts::new(),
+            cache: None,
+            config,
+        });
+        let state: State<'_, CookieFetchState> = app.state();
+        state
+            .sessions
+            .create("main".to_string(), Default::default())
+            .unwrap();
+
+        let cookies = serde_json::from_str(r#"{"127.0.0.1": {"a": {"value": "1"}}}"#).unwrap();
+        let request = CookieFetch::new(app.handle())
+            .get(format!("http://127.0.0.1:{}/", port))
+            .header(
+                HeaderName::from_static("x-token"),
+                HeaderValue::from_static("t"),
+            )
+            .cookies(cookies)
+            .session("main")
+            .response_cookies(ResponseCookies::Changes)
+            .response_type(ResponseType::Text);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let res = runtime.block_on(request.send()).unwrap();
+        let head = handle.join().unwrap();
+
+        assert!(head.contains("x-token: t\r\n"));
+        assert!(head.contains("cookie: a=1\r\n"));
+        assert_eq!(res.status, 200);
+        assert!(matches!(&res.body, super::super::ResponseBody::Text(body) if body == "ok"));
+        let changes = &res.cookies["127.0.0.1"];
+        assert!(matches!(changes["b"].change, Some(CookieChange::Added)));
+        assert!(!changes.contains_key("a"));
+        assert!(state
+            .sessions
+            .get("main")
+            .unwrap()
+            .cookie_store()
+            .contains("127.0.0.1", "/", "b"));
+    }
+}
@@ -1,13 +1,29 @@
-use super::{CookieProps, HeaderMap};
+use super::{Cookies, HeaderMap, RedirectHop, StreamId};
 use bytes::Bytes;
-use std::collections::HashMap;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde_with::skip_serializing_none]
+#[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Response {
     pub url: String,
     pub status: u16,
     pub headers: HeaderMap,
-    pub cookies: HashMap<String, HashMap<String, CookieProps>>,
-    pub body: Bytes,
+    pub cookies: Cookies,
+    pub body: ResponseBody,
+    pub stream: Option<StreamId>,
+    /// `FetchOptions.record_redirects`が指定された場合のみ設定される。
+    pub redirects: Option<Vec<RedirectHop>>,
+    /// ボディを復号した場合の、元の`Content-Encoding`。
+    pub content_encoding: Option<String>,
+    /// ボディを復号した場合の、復号前のバイト数。ストリームの場合は設定されない。
+    pub encoded_length: Option<u64>,
+}
+
+/// `FetchOptions.response_type`に従って復号されたボディ。
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+pub enum ResponseBody {
+    Bytes(Bytes),
+    Text(String),
+    Json(serde_json::Value),
 }
@@ -0,0 +1,12 @@
+/// `Response.cookies`に含めるcookieの範囲。
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseCookies {
+    /// jar内の全てのcookie。
+    #[default]
+    All,
+    /// 最終的なURLに送信されうる、期限切れでないcookie。
+    Url,
+    /// このリクエスト(リダイレクトを含む)で追加・変更・削除されたcookie。
+    Changes,
+}
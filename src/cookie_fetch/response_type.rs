@@ -0,0 +1,107 @@
+use super::{FetchError, ResponseBody};
+use bytes::Bytes;
+
+/// `Response.body`の形式。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseType {
+    #[default]
+    Bytes,
+    /// `Content-Type`のcharsetで復号した文字列。指定がなければUTF-8とみなす。
+    Text,
+    Json,
+}
+
+impl ResponseType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResponseType::Bytes => "bytes",
+            ResponseType::Text => "text",
+            ResponseType::Json => "json",
+        }
+    }
+
+    /// `body`を復号する。`url`と`status`はエラーの報告に用いる。
+    pub fn decode(
+        self,
+        url: &str,
+        status: u16,
+        content_type: Option<&str>,
+        body: Bytes,
+    ) -> Result<ResponseBody, FetchError> {
+        let error = |reason: String| FetchError::Decode {
+            url: url.to_string(),
+            status,
+            response_type: self,
+            reason,
+        };
+
+        match self {
+            ResponseType::Bytes => Ok(ResponseBody::Bytes(body)),
+            ResponseType::Text => decode_text(content_type, &body)
+                .map(ResponseBody::Text)
+                .map_err(error),
+            ResponseType::Json => serde_json::from_slice(&body)
+                .map(ResponseBody::Json)
+                .map_err(|e| error(e.to_string())),
+        }
+    }
+}
+
+/// BOMがあればcharsetより優先する。不正なバイト列は置き換えずにエラーとする。
+fn decode_text(content_type: Option<&str>, body: &[u8]) -> Result<String, String> {
+    let charset = content_type
+        .and_then(|c| c.parse::<mime::Mime>().ok())
+        .and_then(|m| m.get_param(mime::CHARSET).map(|c| c.to_string()));
+    let encoding = match &charset {
+        Some(label) => encoding_rs::Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| format!("unknown charset `{}`", label))?,
+        None => encoding_rs::UTF_8,
+    };
+
+    let (encoding, body) = match encoding_rs::Encoding::for_bom(body) {
+        Some((encoding, len)) => (encoding, &body[len..]),
+        None => (encoding, body),
+    };
+
+    encoding
+        .decode_without_bom_handling_and_without_replacement(body)
+        .map(|s| s.into_owned())
+        .ok_or_else(|| format!("malformed {} text", encoding.name()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_text_by_charset() {
+        let text = decode_text(Some("text/plain; charset=Shift_JIS"), b"\x82\xa0").unwrap();
+        assert_eq!(text, "あ");
+
+        let text =
+            decode_text(Some("text/plain; charset=latin1"), b"\xef\xbb\xbf\xc3\xa9").unwrap();
+        assert_eq!(text, "é");
+
+        assert!(decode_text(None, b"\xff").is_err());
+        assert!(decode_text(Some("text/plain; charset=x-unknown"), b"a").is_err());
+    }
+
+    #[test]
+    fn report_status_on_json_failure() {
+        let e = ResponseType::Json
+            .decode(
+                "https://example.com/api",
+                502,
+                Some("text/html"),
+                Bytes::from_static(b"<html>Bad Gateway</html>"),
+            )
+            .unwrap_err();
+        let json = serde_json::to_value(&e).unwrap();
+
+        assert_eq!(json["kind"], "decode");
+        assert_eq!(json["status"], 502);
+        assert_eq!(json["responseType"], "json");
+        assert_eq!(json["url"], "https://example.com/api");
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+/// リクエストの各段階の制限時間。値はミリ秒で指定する。
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timeouts {
+    /// 接続の開始からボディを読み終えるまで。
+    #[serde(default)]
+    #[serde(with = "millis_serde")]
+    pub timeout: Option<Duration>,
+
+    #[serde(default)]
+    #[serde(with = "millis_serde")]
+    pub connect_timeout: Option<Duration>,
+
+    /// ヘッダ、およびボディの各チャンクを待つ時間。
+    #[serde(default)]
+    #[serde(with = "millis_serde")]
+    pub read_timeout: Option<Duration>,
+}
+
+impl Timeouts {
+    /// 指定されていない値を`defaults`で補う。
+    pub fn or(self, defaults: &Timeouts) -> Timeouts {
+        Timeouts {
+            timeout: self.timeout.or(defaults.timeout),
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            read_timeout: self.read_timeout.or(defaults.read_timeout),
+        }
+    }
+}
+
+/// ミリ秒で指定される制限時間。`#[serde(flatten)]`を避けるため、`Timeouts`の各項目を直接持つ構造体で用いる。
+pub(crate) mod millis_serde {
+    use std::time::Duration;
+
+    type Me = Option<Duration>;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Me, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Me;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("milliseconds")
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Duration::try_from_secs_f64(v / 1000.0)
+                    .map(Some)
+                    .map_err(<E as serde::de::Error>::custom)
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                u64::try_from(v)
+                    .map(Duration::from_millis)
+                    .map(Some)
+                    .map_err(<E as serde::de::Error>::custom)
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Some(Duration::from_millis(v)))
+            }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(None)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// `fut`が`read_timeout`以内に終わらなければ`FetchError::ReadTimeout`で失敗する。
+pub async fn with_read_timeout<T>(
+    read_timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = Result<T, reqwest::Error>>,
+) -> Result<T, super::FetchError> {
+    let res = match read_timeout {
+        Some(d) => tokio::time::timeout(d, fut)
+            .await
+            .map_err(|_| super::FetchError::ReadTimeout)?,
+        None => fut.await,
+    };
+
+    res.map_err(super::FetchError::from_reqwest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fill_unspecified_timeouts() {
+        let timeouts: Timeouts = serde_json::from_str(r#"{"readTimeout":250}"#).unwrap();
+        let defaults: Timeouts =
+            serde_json::from_str(r#"{"timeout":30000,"readTimeout":5000}"#).unwrap();
+        let timeouts = timeouts.or(&defaults);
+
+        assert_eq!(timeouts.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(timeouts.connect_timeout, None);
+        assert_eq!(timeouts.read_timeout, Some(Duration::from_millis(250)));
+    }
+}
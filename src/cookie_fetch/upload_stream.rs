@@ -0,0 +1,205 @@
+use super::FetchError;
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::mpsc;
+
+pub type UploadId = u64;
+
+/// 一度に送りきれないリクエストボディを、webviewから少しずつ受け取る。
+///
+/// `open`で作成したアップロードを`FetchOptions.upload`に渡し、`write`で書き込み、`finish`か`abort`で終える。
+/// いずれも`open`を呼んだウィンドウからしか操作できない。
+pub struct UploadStreams {
+    next_id: AtomicU64,
+    uploads: Mutex<HashMap<UploadId, Upload>>,
+}
+
+struct Upload {
+    /// アップロードを開いたウィンドウのラベル。
+    owner: String,
+    sender: mpsc::Sender<Bytes>,
+    receiver: Option<mpsc::Receiver<Bytes>>,
+    aborted: Arc<AtomicBool>,
+}
+
+const UPLOAD_BUFFER: usize = 16;
+
+impl UploadStreams {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn open(&self, owner: &str) -> UploadId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(UPLOAD_BUFFER);
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.insert(
+            id,
+            Upload {
+                owner: owner.to_string(),
+                sender,
+                receiver: Some(receiver),
+                aborted: Arc::new(AtomicBool::new(false)),
+            },
+        );
+        id
+    }
+
+    /// リクエストのボディとしてアップロードを取り出す。一つのアップロードは一度しか使えない。
+    ///
+    /// 返される`UploadGuard`はリクエストが終わるまで保持する。`finish`が呼ばれないまま失敗した場合も取り除くため。
+    pub fn body(
+        &self,
+        window: &str,
+        id: UploadId,
+    ) -> Result<(reqwest::Body, UploadGuard<'_>), FetchError> {
+        let upload = {
+            let mut uploads = self.uploads.lock().unwrap();
+            match uploads.get_mut(&id) {
+                Some(u) if u.owner != window => return Err(not_allowed(window, id)),
+                Some(u) => u
+                    .receiver
+                    .take()
+                    .map(|receiver| (receiver, Arc::clone(&u.aborted))),
+                None => None,
+            }
+        };
+
+        let Some((receiver, aborted)) = upload else {
+            return Err(FetchError::UploadNotFound(id));
+        };
+
+        // 中断された場合はボディのエラーとしてリクエストを失敗させる。
+        // 単にストリームを終えると、途中までのボディが送信されて正常に完了してしまう。
+        let stream = futures_util::stream::unfold(Some((receiver, aborted)), |state| async move {
+            let (mut receiver, aborted) = state?;
+            let chunk = receiver.recv().await;
+            if aborted.load(Ordering::Acquire) {
+                return Some((Err(UploadAborted), None));
+            }
+            chunk.map(|c| (Ok(c), Some((receiver, aborted))))
+        });
+
+        let guard = UploadGuard { uploads: self, id };
+        Ok((reqwest::Body::wrap_stream(stream), guard))
+    }
+
+    /// チャンクを書き込む。バッファが埋まっている間はリクエストが読み進めるまで待つ。
+    pub async fn write(&self, window: &str, id: UploadId, chunk: Bytes) -> Result<(), FetchError> {
+        let sender = self.sender(window, id)?;
+        sender
+            .send(chunk)
+            .await
+            .map_err(|_| FetchError::UploadClosed(id))
+    }
+
+    pub fn finish(&self, window: &str, id: UploadId) -> Result<(), FetchError> {
+        self.remove(window, id).map(drop)
+    }
+
+    /// アップロードを中断する。送信中のリクエストはボディのエラーとして失敗する。
+    pub fn abort(&self, window: &str, id: UploadId) -> Result<(), FetchError> {
+        let upload = self.remove(window, id)?;
+        upload.aborted.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// 閉じられたウィンドウのアップロードを全て取り除く。
+    pub fn close_window(&self, window: &str) {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.retain(|_, u| u.owner != window);
+    }
+
+    fn remove(&self, window: &str, id: UploadId) -> Result<Upload, FetchError> {
+        let mut uploads = self.uploads.lock().unwrap();
+        match uploads.get(&id) {
+            Some(u) if u.owner != window => Err(not_allowed(window, id)),
+            Some(_) => Ok(uploads.remove(&id).unwrap()),
+            None => Err(FetchError::UploadNotFound(id)),
+        }
+    }
+
+    fn sender(&self, window: &str, id: UploadId) -> Result<mpsc::Sender<Bytes>, FetchError> {
+        let uploads = self.uploads.lock().unwrap();
+        match uploads.get(&id) {
+            Some(u) if u.owner != window => Err(not_allowed(window, id)),
+            Some(u) => Ok(u.sender.clone()),
+            None => Err(FetchError::UploadNotFound(id)),
+        }
+    }
+}
+
+fn not_allowed(window: &str, upload: UploadId) -> FetchError {
+    FetchError::UploadNotAllowed {
+        window: window.to_string(),
+        upload,
+    }
+}
+
+/// 破棄されたときにアップロードを取り除く。
+pub struct UploadGuard<'a> {
+    uploads: &'a UploadStreams,
+    id: UploadId,
+}
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        self.uploads.uploads.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Debug)]
+pub struct UploadAborted;
+
+impl std::fmt::Display for UploadAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("upload aborted")
+    }
+}
+impl std::error::Error for UploadAborted {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_other_windows() {
+        let uploads = UploadStreams::new();
+        let id = uploads.open("main");
+
+        assert!(matches!(
+            uploads.body("other", id),
+            Err(FetchError::UploadNotAllowed { upload, .. }) if upload == id
+        ));
+        assert!(matches!(
+            uploads.abort("other", id),
+            Err(FetchError::UploadNotAllowed { .. })
+        ));
+        let (_, guard) = uploads.body("main", id).unwrap();
+        assert!(uploads.finish("main", id).is_ok());
+        drop(guard);
+    }
+
+    #[test]
+    fn remove_when_request_ends() {
+        let uploads = UploadStreams::new();
+        let id = uploads.open("main");
+
+        let (_, guard) = uploads.body("main", id).unwrap();
+        drop(guard);
+        assert!(uploads.uploads.lock().unwrap().is_empty());
+        assert!(matches!(
+            uploads.finish("main", id),
+            Err(FetchError::UploadNotFound(_))
+        ));
+    }
+}
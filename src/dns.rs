@@ -0,0 +1,33 @@
+use hyper::{
+    client::connect::dns::{GaiResolver, Name},
+    service::Service,
+};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// reqwestの既定と同じ`GaiResolver`で解決し、失敗を接続の失敗と区別できるよう独自の型で返すリゾルバ。
+pub(crate) struct Resolver;
+
+/// 名前解決に失敗した。
+#[derive(Debug)]
+pub(crate) struct ResolveError(std::io::Error);
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to resolve host: {}", self.0)
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = GaiResolver::new().call(name).await.map_err(ResolveError)?;
+            Ok(Box::new(addrs) as Addrs)
+        })
+    }
+}
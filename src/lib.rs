@@ -1,43 +1,495 @@
+mod cache;
 mod config;
 mod cookie_fetch;
+mod dns;
+mod persistence;
+mod proxy;
+mod reply;
 mod scope;
+mod session;
 mod state;
+mod tls;
 
 pub mod cookie_client;
 
-use cookie_client::{CookieClient, CookieClientPool, RedirectPolicy};
-use cookie_fetch::{FetchOptions, Response};
+use bytes::Bytes;
+use cookie_client::{ClientConfig, CookieClient, CookieClientPool, RedirectPolicy};
+use cookie_fetch::{Aborts, BodyStreams, CookieFilter, FetchOptions, UploadId, UploadStreams};
+use persistence::{FlushPolicy, Persistence};
+use reply::Reply;
+use session::{SessionOptions, Sessions};
 use state::CookieFetchState;
-use tauri::{AppHandle, Manager};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, State, Window};
 use tauri_plugin_bin_ipc::{
     bin_command, generate_bin_handler, BinIpcError, PluginBuilderBinIpcExtension,
 };
 
+pub use cache::{CacheConfig, CacheError};
+pub use cookie_fetch::{
+    AbortId, Body, CacheMode, CookieChange, CookieFetch, CookieProps, CookieRejection, Cookies,
+    FetchError, HeaderMap, InvalidCookie, Part, PartContent, Redirect, RedirectHop, RedirectTarget,
+    RequestBuilder, Response, ResponseBody, ResponseCookies, ResponseType, StreamId,
+};
+pub use persistence::{
+    EncryptedStorage, FileStorage, JarStorage, KeyProvider, PersistenceError, StorageError,
+};
+pub use proxy::{ProxyAuth, ProxyConfig};
+pub use scope::{HostPattern, Rule, UrlRule};
+pub use tls::{ClientIdentity, Material, Tls, TlsConfig, TlsError, TlsVersion};
+
 #[bin_command]
 async fn fetch<R: tauri::Runtime>(
     app: AppHandle<R>,
+    window: Window<R>,
     url: String,
     options: Option<FetchOptions>,
-) -> Result<Response, BinIpcError> {
-    let res = cookie_fetch::fetch(app, url, options)
+) -> Result<Reply<Response>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let policy = state.config.policy(window.label());
+    Ok(cookie_fetch::fetch(&state, policy, url, options)
+        .await
+        .into())
+}
+
+#[bin_command]
+async fn read_chunk<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    stream: StreamId,
+) -> Result<Reply<Option<Bytes>>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(state
+        .body_streams
+        .read_chunk(window.label(), stream)
+        .await
+        .into())
+}
+
+#[bin_command]
+async fn cancel<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    stream: StreamId,
+) -> Result<Reply<bool>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(state.body_streams.cancel(window.label(), stream).into())
+}
+
+#[bin_command]
+async fn abort<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: AbortId,
+) -> Result<Reply<bool>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state.aborts.abort(window.label(), id, &state.body_streams);
+    Ok(Reply::Ok(res))
+}
+
+#[bin_command]
+async fn open_upload<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+) -> Result<Reply<UploadId>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(Reply::Ok(state.upload_streams.open(window.label())))
+}
+
+#[bin_command]
+async fn write_chunk<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    upload: UploadId,
+    chunk: Bytes,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(state
+        .upload_streams
+        .write(window.label(), upload, chunk)
         .await
-        .map_err(BinIpcError::new_reportable)?;
+        .into())
+}
+
+#[bin_command]
+async fn finish_upload<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    upload: UploadId,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(state.upload_streams.finish(window.label(), upload).into())
+}
+
+#[bin_command]
+async fn abort_upload<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    upload: UploadId,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    Ok(state.upload_streams.abort(window.label(), upload).into())
+}
+
+#[bin_command]
+async fn create_session<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: String,
+    options: Option<SessionOptions>,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let options = options.unwrap_or_default();
+    let res = state
+        .config
+        .policy(window.label())
+        .check_new_session(&id, &options)
+        .and_then(|()| {
+            state
+                .sessions
+                .create(id, options)
+                .map_err(FetchError::Session)
+        });
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn open_session<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: String,
+    options: Option<SessionOptions>,
+) -> Result<Reply<bool>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let options = options.unwrap_or_default();
+    let res = state
+        .config
+        .policy(window.label())
+        .check_new_session(&id, &options)
+        .and_then(|()| {
+            state
+                .sessions
+                .open(id, options)
+                .map_err(FetchError::Session)
+        });
 
-    Ok(res)
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn close_session<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: String,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .config
+        .policy(window.label())
+        .check_session(&id)
+        .and_then(|()| {
+            let res = state.sessions.close(&id).map_err(FetchError::Session);
+            // 保存に失敗してもセッションは閉じられているため、キャッシュは常に捨てる。
+            if let Some(cache) = &state.cache {
+                cache.remove_session(&id);
+            }
+            res
+        });
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn list_cookies<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    session: String,
+    filter: Option<CookieFilter>,
+) -> Result<Reply<Cookies>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .session(window.label(), &session)
+        .map(|client| cookie_fetch::list_cookies(&client, &filter.unwrap_or_default()));
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn get_cookie<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    session: String,
+    domain: String,
+    path: String,
+    name: String,
+) -> Result<Reply<Option<CookieProps>>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .session(window.label(), &session)
+        .map(|client| cookie_fetch::get_cookie(&client, &domain, &path, &name));
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn set_cookies<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    session: String,
+    cookies: Cookies,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .session(window.label(), &session)
+        .and_then(|client| cookie_fetch::set_cookies(&client, cookies));
+    if res.is_ok() {
+        state.sessions.notify_changed(&session).await;
+    }
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn delete_cookie<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    session: String,
+    domain: String,
+    path: String,
+    name: String,
+) -> Result<Reply<bool>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .session(window.label(), &session)
+        .map(|client| cookie_fetch::delete_cookie(&client, &domain, &path, &name));
+    if res.is_ok() {
+        state.sessions.notify_changed(&session).await;
+    }
+
+    Ok(res.into())
+}
+
+#[bin_command]
+async fn clear_cookies<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    session: String,
+) -> Result<Reply<()>, BinIpcError> {
+    let state: State<'_, CookieFetchState> = app.state();
+    let res = state
+        .session(window.label(), &session)
+        .map(|client| cookie_fetch::clear_cookies(&client));
+    if res.is_ok() {
+        state.sessions.notify_changed(&session).await;
+    }
+
+    Ok(res.into())
 }
 
 const PLUGIN_NAME: &str = "cookie-fetch";
 
 pub fn init<R: tauri::Runtime>() -> tauri::plugin::TauriPlugin<R, config::Config> {
-    tauri::plugin::Builder::new(PLUGIN_NAME)
-        .bin_ipc_handler(PLUGIN_NAME, generate_bin_handler![fetch])
-        .setup_with_config(|app, config| {
-            app.manage(CookieFetchState {
-                client_pool: CookieClientPool::new(),
-                config,
-            });
-
-            Ok(())
-        })
-        .build()
+    Builder::new().build()
+}
+
+/// ホスト側から実行中のプラグインを操作する。
+pub trait CookieFetchExt<R: tauri::Runtime> {
+    /// webviewと同じjarとセッションを使ってリクエストを送る。
+    fn cookie_fetch(&self) -> CookieFetch<R>;
+
+    /// 全体のスコープの`allowlist`に規則を加える。送信待ちのリクエストやリダイレクトにも反映される。
+    ///
+    /// 独自の`scope`を持つウィンドウには影響しない。
+    fn add_allowed(&self, rule: Rule);
+
+    /// 全体のスコープの`allowlist`から`rule`と等しい規則を取り除く。取り除いた場合は`true`を返す。
+    fn remove_allowed(&self, rule: &Rule) -> bool;
+}
+
+impl<R: tauri::Runtime, T: Manager<R>> CookieFetchExt<R> for T {
+    fn cookie_fetch(&self) -> CookieFetch<R> {
+        CookieFetch::new(self.app_handle())
+    }
+
+    fn add_allowed(&self, rule: Rule) {
+        let state: State<'_, CookieFetchState> = self.state();
+        state.config.scope.add_allowed(rule);
+    }
+
+    fn remove_allowed(&self, rule: &Rule) -> bool {
+        let state: State<'_, CookieFetchState> = self.state();
+        state.config.scope.remove_allowed(rule)
+    }
+}
+
+/// 鍵が登録されている場合は`EncryptedStorage`で包む。
+fn encrypted(
+    storage: Box<dyn JarStorage>,
+    key_provider: Option<&Arc<dyn KeyProvider>>,
+) -> Box<dyn JarStorage> {
+    match key_provider {
+        Some(k) => {
+            let k = Arc::clone(k);
+            Box::new(EncryptedStorage::new(storage, Box::new(move || k.key())))
+        }
+        None => storage,
+    }
+}
+
+#[derive(Default)]
+pub struct Builder {
+    storage: Option<Box<dyn JarStorage>>,
+    key_provider: Option<Box<dyn KeyProvider>>,
+    on_persistence_error: Option<persistence::ErrorHandler>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 永続化されたjarの保存先を差し替える。既定ではアプリのデータディレクトリにファイルとして保存する。
+    pub fn storage(mut self, storage: impl JarStorage + 'static) -> Self {
+        self.storage = Some(Box::new(storage));
+        self
+    }
+
+    /// 鍵を登録すると、永続化されたjarと、ディスクに書き出されるキャッシュは暗号化して保存される。
+    pub fn key_provider(mut self, key_provider: impl KeyProvider + 'static) -> Self {
+        self.key_provider = Some(Box::new(key_provider));
+        self
+    }
+
+    /// リクエストの後や定期的な保存など、呼び出し元に返せない永続化の失敗を受け取る。
+    pub fn on_persistence_error(
+        mut self,
+        handler: impl Fn(&str, &PersistenceError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_persistence_error = Some(Box::new(handler));
+        self
+    }
+
+    pub fn build<R: tauri::Runtime>(self) -> tauri::plugin::TauriPlugin<R, config::Config> {
+        let Builder {
+            storage,
+            key_provider,
+            on_persistence_error,
+        } = self;
+
+        tauri::plugin::Builder::new(PLUGIN_NAME)
+            .bin_ipc_handler(
+                PLUGIN_NAME,
+                generate_bin_handler![
+                    fetch,
+                    read_chunk,
+                    cancel,
+                    abort,
+                    open_upload,
+                    write_chunk,
+                    finish_upload,
+                    abort_upload,
+                    create_session,
+                    open_session,
+                    close_session,
+                    list_cookies,
+                    get_cookie,
+                    set_cookies,
+                    delete_cookie,
+                    clear_cookies
+                ],
+            )
+            .setup_with_config(|app, config: config::Config| {
+                // 永続化されたjarとキャッシュで同じ鍵を用いる。
+                let key_provider: Option<Arc<dyn KeyProvider>> = key_provider.map(Arc::from);
+                let persistence = match &config.persistence {
+                    Some(c) => {
+                        let storage: Box<dyn JarStorage> = match storage {
+                            Some(v) => v,
+                            None => {
+                                let Some(dir) = app.path_resolver().app_data_dir() else {
+                                    return Err("failed to resolve the app data directory".into());
+                                };
+                                Box::new(FileStorage::new(dir.join(&c.directory)))
+                            }
+                        };
+                        let storage = encrypted(storage, key_provider.as_ref());
+
+                        let persistence = Persistence::new(storage, c.clone())
+                            .with_error_handler(on_persistence_error);
+                        persistence.verify()?;
+                        Some(persistence)
+                    }
+                    None => None,
+                };
+                let flush_policy = persistence.as_ref().map(Persistence::flush_policy);
+                let client_config = ClientConfig {
+                    proxy: config.proxy.clone(),
+                    tls: config.tls.load()?,
+                };
+                let cache = match &config.cache {
+                    Some(c) => {
+                        let disk = match (&c.directory, &config.persistence) {
+                            (Some(dir), Some(p)) => {
+                                let Some(cache_dir) = app.path_resolver().app_cache_dir() else {
+                                    return Err("failed to resolve the app cache directory".into());
+                                };
+                                let storage = encrypted(
+                                    Box::new(FileStorage::new(cache_dir.join(dir))),
+                                    key_provider.as_ref(),
+                                );
+                                Some(cache::DiskStore::new(storage, p.sessions.clone()))
+                            }
+                            _ => None,
+                        };
+                        Some(cache::HttpCache::new(c.clone(), disk)?)
+                    }
+                    None => None,
+                };
+
+                app.manage(CookieFetchState {
+                    client_pool: CookieClientPool::with_config(client_config.clone()),
+                    sessions: Sessions::new(persistence, client_config),
+                    body_streams: BodyStreams::new(),
+                    upload_streams: UploadStreams::new(),
+                    aborts: Aborts::new(),
+                    cache,
+                    config,
+                });
+
+                if let Some(FlushPolicy::Interval(interval)) = flush_policy {
+                    let app = app.clone();
+                    tauri::async_runtime::spawn(async move {
+                        loop {
+                            tokio::time::sleep(interval).await;
+                            let app = app.clone();
+                            let _ = tokio::task::spawn_blocking(move || {
+                                let state: State<'_, CookieFetchState> = app.state();
+                                let _ = state.sessions.flush_all();
+                            })
+                            .await;
+                        }
+                    });
+                }
+
+                Ok(())
+            })
+            .on_event(|app, event| match event {
+                tauri::RunEvent::Exit => {
+                    let state: State<'_, CookieFetchState> = app.state();
+                    let _ = state.sessions.flush_all();
+                }
+                tauri::RunEvent::WindowEvent {
+                    label,
+                    event: tauri::WindowEvent::Destroyed,
+                    ..
+                } => {
+                    let state: State<'_, CookieFetchState> = app.state();
+                    state.body_streams.close_window(label);
+                    state.upload_streams.close_window(label);
+                }
+                _ => {}
+            })
+            .build()
+    }
 }
@@ -0,0 +1,178 @@
+use super::{JarStorage, StorageError};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+
+/// jarの暗号化に使う256bitの鍵を提供する。
+pub trait KeyProvider: Send + Sync {
+    fn key(&self) -> Result<[u8; 32], Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl<F> KeyProvider for F
+where
+    F: Fn() -> Result<[u8; 32], Box<dyn std::error::Error + Send + Sync>> + Send + Sync,
+{
+    fn key(&self) -> Result<[u8; 32], Box<dyn std::error::Error + Send + Sync>> {
+        self()
+    }
+}
+
+/// 別の`JarStorage`をXChaCha20-Poly1305で包む。
+///
+/// jarの名前を関連データとして認証するため、別のjarのファイルに差し替えられた場合も復号に失敗する。
+pub struct EncryptedStorage {
+    inner: Box<dyn JarStorage>,
+    key_provider: Box<dyn KeyProvider>,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Box<dyn JarStorage>, key_provider: Box<dyn KeyProvider>) -> Self {
+        Self {
+            inner,
+            key_provider,
+        }
+    }
+
+    fn cipher(&self) -> Result<XChaCha20Poly1305, StorageError> {
+        let key = self.key_provider.key().map_err(StorageError::Key)?;
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+}
+
+impl JarStorage for EncryptedStorage {
+    fn load(&self, jar: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        let Some(data) = self.inner.load(jar)? else {
+            return Ok(None);
+        };
+
+        let decrypt_error = || StorageError::Decrypt {
+            jar: jar.to_string(),
+        };
+
+        let Some((&version, rest)) = data.split_first() else {
+            return Err(decrypt_error());
+        };
+        if version != FORMAT_VERSION || rest.len() < NONCE_LEN {
+            return Err(decrypt_error());
+        }
+
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: jar.as_bytes(),
+        };
+        let plaintext = self
+            .cipher()?
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| decrypt_error())?;
+
+        Ok(Some(plaintext))
+    }
+
+    fn save(&self, jar: &str, data: &[u8]) -> Result<(), StorageError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: data,
+            aad: jar.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher()?
+            .encrypt(&nonce, payload)
+            .map_err(|e| StorageError::Other(e.to_string().into()))?;
+
+        let mut buf = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        buf.push(FORMAT_VERSION);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+
+        self.inner.save(jar, &buf)
+    }
+
+    fn remove(&self, jar: &str) -> Result<(), StorageError> {
+        self.inner.remove(jar)
+    }
+
+    fn jars(&self) -> Result<Vec<String>, StorageError> {
+        self.inner.jars()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{collections::HashMap, sync::Mutex};
+
+    #[derive(Default)]
+    struct MemoryStorage(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl JarStorage for MemoryStorage {
+        fn load(&self, jar: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            Ok(self.0.lock().unwrap().get(jar).cloned())
+        }
+
+        fn save(&self, jar: &str, data: &[u8]) -> Result<(), StorageError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(jar.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        fn remove(&self, jar: &str) -> Result<(), StorageError> {
+            self.0.lock().unwrap().remove(jar);
+            Ok(())
+        }
+
+        fn jars(&self) -> Result<Vec<String>, StorageError> {
+            Ok(self.0.lock().unwrap().keys().cloned().collect())
+        }
+    }
+
+    fn storage(key: [u8; 32]) -> EncryptedStorage {
+        EncryptedStorage::new(
+            Box::new(MemoryStorage::default()),
+            Box::new(move || Ok(key)),
+        )
+    }
+
+    #[test]
+    fn roundtrip() {
+        let storage = storage([7; 32]);
+        storage.save("main", b"cookies").unwrap();
+
+        assert_eq!(storage.load("main").unwrap().unwrap(), b"cookies");
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let storage = storage([7; 32]);
+        storage.save("main", b"cookies").unwrap();
+
+        let mut data = storage.inner.load("main").unwrap().unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        storage.inner.save("main", &data).unwrap();
+
+        assert!(matches!(
+            storage.load("main"),
+            Err(StorageError::Decrypt { .. })
+        ));
+    }
+
+    #[test]
+    fn swapped_jar_is_rejected() {
+        let storage = storage([7; 32]);
+        storage.save("main", b"cookies").unwrap();
+
+        let data = storage.inner.load("main").unwrap().unwrap();
+        storage.inner.save("other", &data).unwrap();
+
+        assert!(matches!(
+            storage.load("other"),
+            Err(StorageError::Decrypt { .. })
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+mod encrypted;
+mod storage;
+
+use crate::session::SessionIds;
+use reqwest_cookie_store::CookieStore;
+use std::{io::Write, path::PathBuf, sync::Mutex, time::Duration};
+
+pub use encrypted::{EncryptedStorage, KeyProvider};
+pub use storage::{FileStorage, JarStorage, StorageError};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistenceConfig {
+    #[serde(default)]
+    pub sessions: SessionIds,
+    #[serde(default = "default_directory")]
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub flush: FlushPolicy,
+    /// RFC6265 5.3に従い、Expires/Max-Ageを持たないcookieは既定では保存しない。
+    #[serde(default)]
+    pub keep_session_cookies: bool,
+    #[serde(default)]
+    pub on_corrupted: CorruptionPolicy,
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from("cookies")
+}
+
+/// 保存されたjarが読めない場合の扱い。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CorruptionPolicy {
+    /// jarを破棄して空の状態から始める。
+    Wipe,
+    /// エラーとし、起動時であればアプリの起動を失敗させる。
+    #[default]
+    Fail,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    #[default]
+    Change,
+    Interval(Duration),
+    Exit,
+}
+
+/// リクエストの後などに行う保存が失敗したときに呼ばれる。引数はjarの名前。
+pub type ErrorHandler = Box<dyn Fn(&str, &PersistenceError) + Send + Sync>;
+
+pub struct Persistence {
+    storage: Box<dyn JarStorage>,
+    config: PersistenceConfig,
+    on_error: Option<ErrorHandler>,
+    /// 同じjarへの書き込みが重ならないよう、保存を直列にする。
+    save_lock: Mutex<()>,
+}
+
+impl Persistence {
+    pub fn new(storage: Box<dyn JarStorage>, config: PersistenceConfig) -> Self {
+        Self {
+            storage,
+            config,
+            on_error: None,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn with_error_handler(mut self, on_error: Option<ErrorHandler>) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// 呼び出し元に返せない保存の失敗を報告する。
+    pub fn report(&self, jar: &str, e: &PersistenceError) {
+        if let Some(on_error) = &self.on_error {
+            on_error(jar, e);
+        }
+    }
+
+    pub fn persists(&self, id: &str) -> bool {
+        self.config.sessions.contains(id)
+    }
+
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.config.flush
+    }
+
+    /// 保存されている全てのjarを読み込めるか確かめる。
+    pub fn verify(&self) -> Result<(), PersistenceError> {
+        let jars = self.storage.jars().map_err(PersistenceError::Storage)?;
+        for jar in jars.iter().filter(|j| self.persists(j)) {
+            self.load(jar)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&self, id: &str) -> Result<Option<CookieStore>, PersistenceError> {
+        match self.decode(id) {
+            Err(e) if e.is_corruption() && self.config.on_corrupted == CorruptionPolicy::Wipe => {
+                self.storage.remove(id).map_err(PersistenceError::Storage)?;
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// `store`はjarのロックを返す。ロックはシリアライズした時点で解放し、書き込みの間はリクエストを妨げない。
+    ///
+    /// 古い内容が後から書き込まれないよう、シリアライズから書き込みまでを直列にする。
+    pub fn save<S>(&self, id: &str, store: impl FnOnce() -> S) -> Result<(), PersistenceError>
+    where
+        S: std::ops::Deref<Target = CookieStore>,
+    {
+        let _guard = self.save_lock.lock().unwrap();
+        let buf = self.encode(&store())?;
+        self.storage
+            .save(id, &buf)
+            .map_err(PersistenceError::Storage)
+    }
+
+    fn encode(&self, store: &CookieStore) -> Result<Vec<u8>, PersistenceError> {
+        let mut buf = Vec::new();
+        for cookie in store.iter_unexpired() {
+            if !cookie.is_persistent() && !self.config.keep_session_cookies {
+                continue;
+            }
+
+            let line =
+                serde_json::to_string(cookie).map_err(|e| PersistenceError::Format(e.into()))?;
+            writeln!(buf, "{}", line).map_err(|e| PersistenceError::Format(e.into()))?;
+        }
+
+        Ok(buf)
+    }
+
+    fn decode(&self, id: &str) -> Result<Option<CookieStore>, PersistenceError> {
+        let Some(data) = self.storage.load(id).map_err(PersistenceError::Storage)? else {
+            return Ok(None);
+        };
+
+        // 期限切れのcookieは読み込み時に捨てられる。
+        let store = CookieStore::load_json(&data[..]).map_err(PersistenceError::Format)?;
+
+        Ok(Some(store))
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Storage(StorageError),
+    Format(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl PersistenceError {
+    fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            PersistenceError::Storage(StorageError::Decrypt { .. }) | PersistenceError::Format(_)
+        )
+    }
+}
+
+impl std::fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistenceError::Storage(e) => <_ as std::fmt::Display>::fmt(e, f),
+            PersistenceError::Format(e) => write!(f, "malformed cookie jar: {}", e),
+        }
+    }
+}
+impl std::error::Error for PersistenceError {}
+
+impl<'de> serde::Deserialize<'de> for FlushPolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct KeyInterval;
+        impl<'de> serde::de::Deserialize<'de> for KeyInterval {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct V;
+                impl<'de> serde::de::Visitor<'de> for V {
+                    type Value = KeyInterval;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        formatter.write_str("`interval`")
+                    }
+
+                    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+                    where
+                        E: serde::de::Error,
+                    {
+                        match v {
+                            "interval" => Ok(KeyInterval),
+                            _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                        }
+                    }
+                }
+
+                deserializer.deserialize_str(V)
+            }
+        }
+
+        struct V;
+        impl<'de> serde::de::Visitor<'de> for V {
+            type Value = FlushPolicy;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("`change`, `exit`, or `{ interval: seconds }`")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match v {
+                    "change" => Ok(FlushPolicy::Change),
+                    "exit" => Ok(FlushPolicy::Exit),
+                    _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                }
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let Some((_, secs)) = map.next_entry::<KeyInterval, f64>()? else {
+                    return Err(<A::Error as serde::de::Error>::missing_field("interval"));
+                };
+
+                let interval = Duration::try_from_secs_f64(secs)
+                    .map_err(<A::Error as serde::de::Error>::custom)?;
+
+                Ok(FlushPolicy::Interval(interval))
+            }
+        }
+
+        deserializer.deserialize_any(V)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 並行して実行される他のテストと衝突しないディレクトリ。
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        std::env::temp_dir().join(format!(
+            "tauri-plugin-cookie-fetch-{}-{}-{}",
+            name,
+            std::process::id(),
+            nanos
+        ))
+    }
+
+    #[test]
+    fn deserialize_flush_policy() {
+        let policy: FlushPolicy = serde_json::from_str(r#""exit""#).unwrap();
+        assert_eq!(policy, FlushPolicy::Exit);
+
+        let policy: FlushPolicy = serde_json::from_str(r#"{"interval":30}"#).unwrap();
+        assert_eq!(policy, FlushPolicy::Interval(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn save_and_load_skips_session_cookies() {
+        let dir = temp_dir("persistence");
+        let persistence = Persistence::new(
+            Box::new(FileStorage::new(&dir)),
+            serde_json::from_str(r#"{"sessions":["main"]}"#).unwrap(),
+        );
+
+        let url = reqwest::Url::parse("https://example.com/").unwrap();
+        let mut store = CookieStore::new(None);
+        store.parse("persistent=1; Max-Age=3600", &url).unwrap();
+        store.parse("session=1", &url).unwrap();
+
+        persistence.save("main", || &store).unwrap();
+        let loaded = persistence.load("main").unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(loaded.contains("example.com", "/", "persistent"));
+        assert!(!loaded.contains("example.com", "/", "session"));
+        assert!(persistence.persists("main"));
+        assert!(!persistence.persists("other"));
+    }
+
+    #[test]
+    fn corrupted_jar_is_wiped() {
+        let dir = temp_dir("corruption");
+        let storage = FileStorage::new(&dir);
+        storage.save("main", b"not a cookie").unwrap();
+
+        let persistence = Persistence::new(
+            Box::new(storage),
+            serde_json::from_str(r#"{"onCorrupted":"wipe"}"#).unwrap(),
+        );
+        let loaded = persistence.load("main").unwrap();
+        let jars = FileStorage::new(&dir).jars().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(loaded.is_none());
+        assert!(jars.is_empty());
+    }
+}
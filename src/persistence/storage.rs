@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+/// 永続化されたcookie jarの保存先。jarはシリアライズ済みのバイト列として渡される。
+pub trait JarStorage: Send + Sync {
+    fn load(&self, jar: &str) -> Result<Option<Vec<u8>>, StorageError>;
+    fn save(&self, jar: &str, data: &[u8]) -> Result<(), StorageError>;
+    fn remove(&self, jar: &str) -> Result<(), StorageError>;
+    fn jars(&self) -> Result<Vec<String>, StorageError>;
+}
+
+/// jarごとに一つのファイルへ書き出す。
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// jarの名前はファイル名に使えない文字を含みうるため、16進表記にしてファイル名とする。
+    fn jar_path(&self, jar: &str) -> PathBuf {
+        let name: String = jar.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.directory.join(format!("{}.json", name))
+    }
+}
+
+impl JarStorage for FileStorage {
+    fn load(&self, jar: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match std::fs::read(self.jar_path(jar)) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    fn save(&self, jar: &str, data: &[u8]) -> Result<(), StorageError> {
+        std::fs::create_dir_all(&self.directory).map_err(StorageError::Io)?;
+
+        let path = self.jar_path(jar);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, data).map_err(StorageError::Io)?;
+        std::fs::rename(&tmp, &path).map_err(StorageError::Io)?;
+
+        Ok(())
+    }
+
+    fn remove(&self, jar: &str) -> Result<(), StorageError> {
+        match std::fs::remove_file(self.jar_path(jar)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    fn jars(&self) -> Result<Vec<String>, StorageError> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(StorageError::Io(e)),
+        };
+
+        let mut jars = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(StorageError::Io)?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str().and_then(|n| n.strip_suffix(".json")) else {
+                continue;
+            };
+
+            if let Some(jar) = decode_hex(name) {
+                jars.push(jar);
+            }
+        }
+
+        Ok(jars)
+    }
+}
+
+fn decode_hex(s: &str) -> Option<String> {
+    let bytes = s
+        .as_bytes()
+        .chunks(2)
+        .map(|c| {
+            let c = std::str::from_utf8(c).ok().filter(|c| c.len() == 2)?;
+            u8::from_str_radix(c, 16).ok()
+        })
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Key(Box<dyn std::error::Error + Send + Sync>),
+    /// 改竄された、もしくは鍵が異なるため復号できない。
+    Decrypt {
+        jar: String,
+    },
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "failed to access cookie jar storage: {}", e),
+            StorageError::Key(e) => write!(f, "failed to get cookie jar key: {}", e),
+            StorageError::Decrypt { jar } => {
+                write!(f, "cookie jar `{}` is tampered or undecryptable", jar)
+            }
+            StorageError::Other(e) => <_ as std::fmt::Display>::fmt(e, f),
+        }
+    }
+}
+impl std::error::Error for StorageError {}
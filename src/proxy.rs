@@ -0,0 +1,142 @@
+/// プロキシの設定。
+///
+/// プロキシのURLには`http://`、`https://`、`socks5://`、`socks5h://`を指定できる。
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// 全てのリクエストに用いるプロキシ。`http`、`https`より優先される。
+    #[serde(default)]
+    pub all: Option<String>,
+    /// `http://`へのリクエストに用いるプロキシ。
+    #[serde(default)]
+    pub http: Option<String>,
+    /// `https://`へのリクエストに用いるプロキシ。
+    #[serde(default)]
+    pub https: Option<String>,
+    #[serde(default)]
+    pub auth: Option<ProxyAuth>,
+    /// プロキシを通さないホスト。`NO_PROXY`環境変数と同じ形式で指定する。
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    /// `HTTP_PROXY`、`HTTPS_PROXY`、`ALL_PROXY`、`NO_PROXY`環境変数に従う。
+    ///
+    /// プロキシが明示的に指定された場合は無視される。
+    #[serde(default)]
+    pub system: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyConfig {
+    fn proxies(&self) -> Result<Vec<reqwest::Proxy>, reqwest::Error> {
+        let mut proxies = Vec::new();
+        if let Some(url) = &self.all {
+            proxies.push(reqwest::Proxy::all(url)?);
+        }
+        if let Some(url) = &self.http {
+            proxies.push(reqwest::Proxy::http(url)?);
+        }
+        if let Some(url) = &self.https {
+            proxies.push(reqwest::Proxy::https(url)?);
+        }
+
+        let no_proxy = reqwest::NoProxy::from_string(&self.no_proxy.join(","));
+        Ok(proxies
+            .into_iter()
+            .map(|p| {
+                let p = match &self.auth {
+                    Some(auth) => p.basic_auth(&auth.username, &auth.password),
+                    None => p,
+                };
+                p.no_proxy(no_proxy.clone())
+            })
+            .collect())
+    }
+
+    /// `builder`にプロキシを設定する。
+    pub(crate) fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, reqwest::Error> {
+        let proxies = self.proxies()?;
+        if proxies.is_empty() && !self.system {
+            return Ok(builder.no_proxy());
+        }
+
+        for proxy in proxies {
+            builder = builder.proxy(proxy);
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cookie_client::ClientConfig, CookieClient};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    /// 一つのリクエストを受け取り、その先頭部分を返すプロキシの代わり。
+    fn stand_in() -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                head.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok")
+                .unwrap();
+            String::from_utf8(head).unwrap()
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn send_through_proxy() {
+        let (url, handle) = stand_in();
+        let proxy: ProxyConfig = serde_json::from_value(serde_json::json!({
+            "all": url,
+            "auth": { "username": "user", "password": "pass" },
+        }))
+        .unwrap();
+        let config = ClientConfig {
+            proxy,
+            ..Default::default()
+        };
+        let client =
+            CookieClient::with_config(reqwest_cookie_store::CookieStore::new(None), &config)
+                .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let body = runtime.block_on(async {
+            let res = client
+                .request(reqwest::Method::GET, "http://example.invalid/path")
+                .send()
+                .await
+                .unwrap();
+            res.text().await.unwrap()
+        });
+
+        let head = handle.join().unwrap().to_ascii_lowercase();
+        assert_eq!(body, "ok");
+        assert!(head.starts_with("get http://example.invalid/path http/1.1\r\n"));
+        assert!(head.contains("proxy-authorization: basic dxnlcjpwyxnz\r\n"));
+    }
+}
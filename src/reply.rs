@@ -0,0 +1,32 @@
+use crate::cookie_fetch::FetchError;
+
+/// bin-ipcのエラーは文字列しか運べないため、コマンドの結果を`{ ok }`か`{ err }`として返す。
+pub enum Reply<T> {
+    Ok(T),
+    Err(FetchError),
+}
+
+impl<T> From<Result<T, FetchError>> for Reply<T> {
+    fn from(value: Result<T, FetchError>) -> Self {
+        match value {
+            Ok(v) => Reply::Ok(v),
+            Err(e) => Reply::Err(e),
+        }
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for Reply<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            Reply::Ok(v) => map.serialize_entry("ok", v)?,
+            Reply::Err(e) => map.serialize_entry("err", e)?,
+        }
+        map.end()
+    }
+}
@@ -1,47 +1,372 @@
+use reqwest::header::HeaderName;
+use std::sync::RwLock;
+
+/// リクエストを送ってよい範囲。
+///
+/// `denylist`のいずれかに当てはまるリクエストは、`allowlist`に関わらず拒否される。
 #[derive(Debug, serde::Deserialize, Default)]
 pub struct Scope {
-    #[serde(deserialize_with = "deserialize_patterns")]
     #[serde(default)]
-    pub allowlist: Vec<glob::Pattern>,
+    pub allowlist: Vec<Rule>,
+    /// `headers`は許可するヘッダを表すため、`denylist`の規則には指定できない。
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_denylist")]
+    pub denylist: Vec<Rule>,
 }
 
 impl Scope {
-    pub fn is_allowed(&self, url: &reqwest::Url) -> bool {
-        self.allowlist.iter().any(|pat| pat.matches(url.as_str()))
+    pub fn allows(&self, request: &reqwest::Request) -> bool {
+        self.allows_except(request, &[])
+    }
+
+    /// `implied`はボディから付けられた`Content-Type`など、利用者が指定していないヘッダ。`UrlRule.headers`と照合しない。
+    pub fn allows_except(&self, request: &reqwest::Request, implied: &[HeaderName]) -> bool {
+        !self.denylist.iter().any(|r| r.matches(request, implied))
+            && self.allowlist.iter().any(|r| r.matches(request, implied))
     }
 }
 
-fn deserialize_patterns<'de, D>(deserializer: D) -> Result<Vec<glob::Pattern>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    struct Visitor;
-    impl<'de> serde::de::Visitor<'de> for Visitor {
-        type Value = Vec<glob::Pattern>;
+/// 実行中に変更できるスコープ。判定のたびに読み直すため、変更は送信待ちのリクエストにも反映される。
+#[derive(Debug, Default)]
+pub struct SharedScope(RwLock<Scope>);
+
+impl SharedScope {
+    pub fn allows(&self, request: &reqwest::Request) -> bool {
+        self.0.read().unwrap().allows(request)
+    }
+
+    pub fn allows_except(&self, request: &reqwest::Request, implied: &[HeaderName]) -> bool {
+        self.0.read().unwrap().allows_except(request, implied)
+    }
+
+    pub fn add_allowed(&self, rule: Rule) {
+        self.0.write().unwrap().allowlist.push(rule);
+    }
+
+    /// `rule`と等しい規則を全て取り除く。取り除いた場合は`true`を返す。
+    pub fn remove_allowed(&self, rule: &Rule) -> bool {
+        let mut scope = self.0.write().unwrap();
+        let len = scope.allowlist.len();
+        scope.allowlist.retain(|r| r != rule);
+        scope.allowlist.len() != len
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SharedScope {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        <Scope as serde::Deserialize>::deserialize(deserializer)
+            .map(|s| SharedScope(RwLock::new(s)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rule {
+    /// URL全体に対するglob。以前の形式との互換のために残している。
+    Glob(glob::Pattern),
+    Url(UrlRule),
+}
+
+impl Rule {
+    pub fn glob(pattern: &str) -> Result<Self, glob::PatternError> {
+        glob::Pattern::new(pattern).map(Rule::Glob)
+    }
+
+    fn matches(&self, request: &reqwest::Request, implied: &[HeaderName]) -> bool {
+        match self {
+            Rule::Glob(pattern) => pattern.matches(request.url().as_str()),
+            Rule::Url(rule) => rule.matches(request, implied),
+        }
+    }
+}
 
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("cookie SameSite")
+/// URLの各部分を個別に照合する規則。指定されなかった部分は何にでも一致する。
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct UrlRule {
+    #[serde(default)]
+    pub scheme: Option<String>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_host")]
+    pub host: Option<HostPattern>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_path")]
+    pub path: Option<glob::Pattern>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_methods")]
+    pub methods: Option<Vec<reqwest::Method>>,
+    /// リクエストが持ってよいヘッダの名前。これ以外のヘッダを持つリクエストには一致しない。
+    ///
+    /// 利用者が指定したヘッダのみを照合し、`body`から付けられる`Content-Type`は含めない。
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_headers")]
+    pub headers: Option<Vec<HeaderName>>,
+}
+
+impl UrlRule {
+    fn matches(&self, request: &reqwest::Request, implied: &[HeaderName]) -> bool {
+        let url = request.url();
+
+        self.scheme
+            .as_deref()
+            .is_none_or(|s| s.eq_ignore_ascii_case(url.scheme()))
+            && self
+                .host
+                .as_ref()
+                .is_none_or(|h| url.host_str().is_some_and(|host| h.matches(host)))
+            && self
+                .port
+                .is_none_or(|p| url.port_or_known_default() == Some(p))
+            && self.path.as_ref().is_none_or(|p| p.matches(url.path()))
+            && self
+                .methods
+                .as_ref()
+                .is_none_or(|m| m.contains(request.method()))
+            && self.headers.as_ref().is_none_or(|h| {
+                request
+                    .headers()
+                    .keys()
+                    .filter(|k| !implied.contains(k))
+                    .all(|k| h.contains(k))
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// `*`
+    Any,
+    /// `*.example.com`。`example.com`自体には一致しない。
+    Subdomains(String),
+    Exact(String),
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+        if pattern == "*" {
+            return HostPattern::Any;
+        }
+
+        match pattern.strip_prefix("*.") {
+            Some(domain) => HostPattern::Subdomains(domain.to_string()),
+            None => HostPattern::Exact(pattern),
+        }
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        match self {
+            HostPattern::Any => true,
+            HostPattern::Subdomains(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            HostPattern::Exact(h) => host == *h,
         }
+    }
+}
 
-        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-        where
-            A: serde::de::SeqAccess<'de>,
-        {
-            let mut buf = match seq.size_hint() {
-                Some(len) => Vec::with_capacity(len),
-                None => Vec::new(),
-            };
+impl<'de> serde::Deserialize<'de> for Rule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Rule;
 
-            while let Some(pattern) = seq.next_element::<String>()? {
-                let pattern =
-                    glob::Pattern::new(&pattern).map_err(<A::Error as serde::de::Error>::custom)?;
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("url glob or scope rule")
+            }
 
-                buf.push(pattern);
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                glob::Pattern::new(v)
+                    .map(Rule::Glob)
+                    .map_err(<E as serde::de::Error>::custom)
             }
 
-            Ok(buf)
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let deserializer = serde::de::value::MapAccessDeserializer::new(map);
+                <UrlRule as serde::Deserialize>::deserialize(deserializer).map(Rule::Url)
+            }
         }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn deserialize_denylist<'de, D>(deserializer: D) -> Result<Vec<Rule>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let rules = <Vec<Rule> as serde::Deserialize>::deserialize(deserializer)?;
+    if rules.iter().any(|r| {
+        matches!(
+            r,
+            Rule::Url(UrlRule {
+                headers: Some(_),
+                ..
+            })
+        )
+    }) {
+        return Err(<D::Error as serde::de::Error>::custom(
+            "`headers` cannot be used in denylist rules",
+        ));
+    }
+
+    Ok(rules)
+}
+
+fn deserialize_host<'de, D>(deserializer: D) -> Result<Option<HostPattern>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let host = <Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(host.as_deref().map(HostPattern::parse))
+}
+
+fn deserialize_path<'de, D>(deserializer: D) -> Result<Option<glob::Pattern>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(path) = <Option<String> as serde::Deserialize>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    glob::Pattern::new(&path)
+        .map(Some)
+        .map_err(<D::Error as serde::de::Error>::custom)
+}
+
+fn deserialize_methods<'de, D>(deserializer: D) -> Result<Option<Vec<reqwest::Method>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(methods) = <Option<Vec<String>> as serde::Deserialize>::deserialize(deserializer)?
+    else {
+        return Ok(None);
+    };
+
+    methods
+        .iter()
+        .map(|m| reqwest::Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(<D::Error as serde::de::Error>::custom)
+}
+
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<Option<Vec<HeaderName>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(headers) = <Option<Vec<String>> as serde::Deserialize>::deserialize(deserializer)?
+    else {
+        return Ok(None);
+    };
+
+    headers
+        .iter()
+        .map(|h| HeaderName::from_bytes(h.as_bytes()))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(<D::Error as serde::de::Error>::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(method: reqwest::Method, url: &str) -> reqwest::Request {
+        reqwest::Request::new(method, url.parse().unwrap())
+    }
+
+    #[test]
+    fn subdomain_wildcard_stays_in_host() {
+        let scope: Scope = serde_json::from_str(
+            r#"{"allowlist":[{"scheme":"https","host":"*.example.com","path":"/api/*"}]}"#,
+        )
+        .unwrap();
+
+        let get = reqwest::Method::GET;
+        assert!(scope.allows(&request(get.clone(), "https://a.example.com/api/v1")));
+        assert!(!scope.allows(&request(get.clone(), "https://example.com/api/v1")));
+        assert!(!scope.allows(&request(
+            get.clone(),
+            "https://a.example.com.evil.io/api/v1"
+        )));
+        assert!(!scope.allows(&request(get, "http://a.example.com/api/v1")));
     }
 
-    deserializer.deserialize_seq(Visitor)
+    #[test]
+    fn denylist_takes_precedence() {
+        let scope: Scope = serde_json::from_str(
+            r#"{
+                "allowlist":["https://example.com/*"],
+                "denylist":[{"host":"example.com","path":"/admin/*","methods":["post"]}]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(scope.allows(&request(
+            reqwest::Method::GET,
+            "https://example.com/admin/users"
+        )));
+        assert!(!scope.allows(&request(
+            reqwest::Method::POST,
+            "https://example.com/admin/users"
+        )));
+    }
+
+    #[test]
+    fn reject_headers_in_denylist() {
+        let result = serde_json::from_str::<Scope>(
+            r#"{"denylist":[{"host":"example.com","headers":["authorization"]}]}"#,
+        );
+        assert!(result.is_err());
+
+        let result = serde_json::from_str::<Scope>(
+            r#"{"allowlist":[{"host":"example.com","headers":["authorization"]}]}"#,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn match_only_caller_headers() {
+        let scope: Scope = serde_json::from_str(
+            r#"{"allowlist":[{"host":"example.com","headers":["authorization"]}]}"#,
+        )
+        .unwrap();
+
+        let mut request = request(reqwest::Method::POST, "https://example.com/");
+        request.headers_mut().insert(
+            reqwest::header::CONTENT_TYPE,
+            "application/json".parse().unwrap(),
+        );
+        assert!(!scope.allows(&request));
+        assert!(scope.allows_except(&request, &[reqwest::header::CONTENT_TYPE]));
+    }
+
+    #[test]
+    fn modify_shared_scope() {
+        let scope = SharedScope::default();
+        let request = request(reqwest::Method::GET, "https://example.com/");
+        assert!(!scope.allows(&request));
+
+        scope.add_allowed(Rule::glob("https://example.com/*").unwrap());
+        assert!(scope.allows(&request));
+
+        assert!(scope.remove_allowed(&Rule::glob("https://example.com/*").unwrap()));
+        assert!(!scope.allows(&request));
+        assert!(!scope.remove_allowed(&Rule::glob("https://example.com/*").unwrap()));
+    }
 }
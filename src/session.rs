@@ -0,0 +1,387 @@
+use crate::{
+    cookie_client::ClientConfig,
+    persistence::{FlushPolicy, Persistence, PersistenceError},
+    CookieClient, ProxyConfig,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// 名前付きセッション。セッションごとに一つの`CookieClient`を保持し、閉じられるまでcookieを引き継ぐ。
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Arc<CookieClient>>>,
+    persistence: Option<Arc<Persistence>>,
+    /// `SessionOptions`で上書きされなかった項目に用いる設定。
+    config: ClientConfig,
+}
+
+/// セッションを作成するときの設定。
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionOptions {
+    /// 全体の`proxy`の代わりに用いる。`WindowPolicy.session_proxy`を許可されたウィンドウでのみ指定できる。
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+}
+
+impl Sessions {
+    pub fn new(persistence: Option<Persistence>, config: ClientConfig) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            persistence: persistence.map(Arc::new),
+            config,
+        }
+    }
+
+    pub fn create(&self, id: String, options: SessionOptions) -> Result<(), SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.entry(id) {
+            Entry::Occupied(e) => Err(SessionError::AlreadyExists(e.key().clone())),
+            Entry::Vacant(e) => {
+                let client = self.new_client(e.key(), options)?;
+                e.insert(Arc::new(client));
+                Ok(())
+            }
+        }
+    }
+
+    /// セッションが存在しなければ作成する。新たに作成した場合は`true`を返す。
+    /// 既に存在するセッションの設定は変更しない。
+    pub fn open(&self, id: String, options: SessionOptions) -> Result<bool, SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.entry(id) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(e) => {
+                let client = self.new_client(e.key(), options)?;
+                e.insert(Arc::new(client));
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn close(&self, id: &str) -> Result<(), SessionError> {
+        let client = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.remove(id)
+        };
+
+        match client {
+            Some(client) => self.save(id, &client),
+            None => Err(SessionError::NotFound(id.to_string())),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<CookieClient>> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(id).cloned()
+    }
+
+    /// セッションのcookieが変更された可能性があるときに呼ぶ。
+    ///
+    /// 保存はブロッキングスレッドで行い、失敗は呼び出し元に返さず`Persistence::report`で報告する。
+    pub async fn notify_changed(&self, id: &str) {
+        let Some(persistence) = &self.persistence else {
+            return;
+        };
+        if persistence.flush_policy() != FlushPolicy::Change || !persistence.persists(id) {
+            return;
+        }
+        let Some(client) = self.get(id) else {
+            return;
+        };
+
+        let persistence = Arc::clone(persistence);
+        let id = id.to_string();
+        let _ = tokio::task::spawn_blocking(move || {
+            if let Err(e) = persistence.save(&id, || client.cookie_store()) {
+                persistence.report(&id, &e);
+            }
+        })
+        .await;
+    }
+
+    pub fn flush_all(&self) -> Result<(), SessionError> {
+        let sessions: Vec<_> = {
+            let sessions = self.sessions.lock().unwrap();
+            sessions
+                .iter()
+                .map(|(id, client)| (id.clone(), Arc::clone(client)))
+                .collect()
+        };
+
+        // 一つの失敗で残りのjarを保存し損ねないよう、全て試みてから最初の失敗を返す。
+        let mut result = Ok(());
+        for (id, client) in sessions {
+            if let Err(e) = self.save(&id, &client) {
+                if let (SessionError::Persistence(e), Some(p)) = (&e, &self.persistence) {
+                    p.report(&id, e);
+                }
+                result = result.and(Err(e));
+            }
+        }
+
+        result
+    }
+
+    fn new_client(&self, id: &str, options: SessionOptions) -> Result<CookieClient, SessionError> {
+        let store = match &self.persistence {
+            Some(p) if p.persists(id) => p.load(id).map_err(SessionError::Persistence)?,
+            _ => None,
+        };
+
+        let store = store.unwrap_or_else(|| reqwest_cookie_store::CookieStore::new(None));
+        let client = match options.proxy {
+            Some(proxy) => CookieClient::with_config(
+                store,
+                &ClientConfig {
+                    proxy,
+                    ..self.config.clone()
+                },
+            ),
+            None => CookieClient::with_config(store, &self.config),
+        };
+        client.map_err(SessionError::Reqwest)
+    }
+
+    fn save(&self, id: &str, client: &CookieClient) -> Result<(), SessionError> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(());
+        };
+
+        if !persistence.persists(id) {
+            return Ok(());
+        }
+
+        persistence
+            .save(id, || client.cookie_store())
+            .map_err(SessionError::Persistence)
+    }
+}
+
+/// `"all"`もしくはセッションIDの列で指定されるセッションの集合。
+#[derive(Debug, Default, Clone)]
+pub enum SessionIds {
+    #[default]
+    All,
+    Only(Vec<String>),
+}
+
+impl SessionIds {
+    pub fn contains(&self, id: &str) -> bool {
+        match self {
+            SessionIds::All => true,
+            SessionIds::Only(ids) => ids.iter().any(|e| e == id),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SessionIds {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct V;
+        impl<'de> serde::de::Visitor<'de> for V {
+            type Value = SessionIds;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("`all` or sequence of session ids")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match v {
+                    "all" => Ok(SessionIds::All),
+                    _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                }
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut buf = match seq.size_hint() {
+                    Some(len) => Vec::with_capacity(len),
+                    None => Vec::new(),
+                };
+
+                while let Some(id) = seq.next_element::<String>()? {
+                    buf.push(id);
+                }
+
+                Ok(SessionIds::Only(buf))
+            }
+        }
+
+        deserializer.deserialize_any(V)
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Reqwest(reqwest::Error),
+    Persistence(PersistenceError),
+    AlreadyExists(String),
+    NotFound(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Reqwest(e) => <_ as std::fmt::Display>::fmt(e, f),
+            SessionError::Persistence(e) => <_ as std::fmt::Display>::fmt(e, f),
+            SessionError::AlreadyExists(id) => write!(f, "session `{}` already exists", id),
+            SessionError::NotFound(id) => write!(f, "session `{}` not found", id),
+        }
+    }
+}
+impl std::error::Error for SessionError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::FileStorage;
+
+    fn insert(client: &CookieClient, cookie: &str) {
+        let url = reqwest::Url::parse("https://example.com/").unwrap();
+        client.cookie_store().parse(cookie, &url).unwrap();
+    }
+
+    #[test]
+    fn create_open_and_close() {
+        let sessions = Sessions::new(None, ClientConfig::default());
+
+        sessions.create("main".into(), Default::default()).unwrap();
+        assert!(matches!(
+            sessions.create("main".into(), Default::default()),
+            Err(SessionError::AlreadyExists(id)) if id == "main"
+        ));
+        assert!(!sessions.open("main".into(), Default::default()).unwrap());
+        assert!(sessions.open("sub".into(), Default::default()).unwrap());
+
+        // 同じセッションは同じjarを共有する。
+        insert(&sessions.get("main").unwrap(), "id=1");
+        assert!(sessions
+            .get("main")
+            .unwrap()
+            .cookie_store()
+            .contains("example.com", "/", "id"));
+        assert!(!sessions
+            .get("sub")
+            .unwrap()
+            .cookie_store()
+            .contains("example.com", "/", "id"));
+
+        sessions.close("main").unwrap();
+        assert!(sessions.get("main").is_none());
+        assert!(matches!(
+            sessions.close("main"),
+            Err(SessionError::NotFound(id)) if id == "main"
+        ));
+
+        // 永続化しない場合、閉じたセッションのcookieは失われる。
+        sessions.create("main".into(), Default::default()).unwrap();
+        assert!(sessions
+            .get("main")
+            .unwrap()
+            .cookie_store()
+            .iter_any()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn flush_and_restore_persisted_sessions() {
+        let dir = std::env::temp_dir().join(format!(
+            "tauri-plugin-cookie-fetch-sessions-{}",
+            std::process::id()
+        ));
+        let persistence = || {
+            Persistence::new(
+                Box::new(FileStorage::new(&dir)),
+                serde_json::from_str(r#"{"sessions":["main"],"flush":"exit"}"#).unwrap(),
+            )
+        };
+
+        let sessions = Sessions::new(Some(persistence()), ClientConfig::default());
+        sessions.create("main".into(), Default::default()).unwrap();
+        sessions.create("other".into(), Default::default()).unwrap();
+        insert(&sessions.get("main").unwrap(), "id=1; Max-Age=3600");
+        insert(&sessions.get("other").unwrap(), "id=2; Max-Age=3600");
+        sessions.flush_all().unwrap();
+
+        let restored = Sessions::new(Some(persistence()), ClientConfig::default());
+        restored.create("main".into(), Default::default()).unwrap();
+        restored.create("other".into(), Default::default()).unwrap();
+        let main = restored.get("main").unwrap();
+        let other = restored.get("other").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(main.cookie_store().contains("example.com", "/", "id"));
+        assert!(!other.cookie_store().contains("example.com", "/", "id"));
+    }
+
+    #[test]
+    fn report_failed_saves() {
+        use crate::persistence::{JarStorage, StorageError};
+
+        struct FailingStorage;
+
+        impl JarStorage for FailingStorage {
+            fn load(&self, _: &str) -> Result<Option<Vec<u8>>, StorageError> {
+                Ok(None)
+            }
+
+            fn save(&self, _: &str, _: &[u8]) -> Result<(), StorageError> {
+                Err(StorageError::Other("disk full".into()))
+            }
+
+            fn remove(&self, _: &str) -> Result<(), StorageError> {
+                Ok(())
+            }
+
+            fn jars(&self) -> Result<Vec<String>, StorageError> {
+                Ok(Vec::new())
+            }
+        }
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let on_error = {
+            let reported = Arc::clone(&reported);
+            move |jar: &str, e: &PersistenceError| {
+                reported
+                    .lock()
+                    .unwrap()
+                    .push((jar.to_string(), e.to_string()));
+            }
+        };
+        let persistence = Persistence::new(
+            Box::new(FailingStorage),
+            serde_json::from_str("{}").unwrap(),
+        )
+        .with_error_handler(Some(Box::new(on_error)));
+        let sessions = Sessions::new(Some(persistence), ClientConfig::default());
+        sessions.create("main".into(), Default::default()).unwrap();
+        sessions.create("sub".into(), Default::default()).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(sessions.notify_changed("main"));
+        assert!(sessions.flush_all().is_err());
+
+        let mut reported = reported.lock().unwrap().clone();
+        reported.sort();
+        assert_eq!(
+            reported,
+            [
+                ("main".to_string(), "disk full".to_string()),
+                ("main".to_string(), "disk full".to_string()),
+                ("sub".to_string(), "disk full".to_string()),
+            ]
+        );
+    }
+}
@@ -1,6 +1,33 @@
-use crate::CookieClientPool;
+use crate::{
+    cache::HttpCache,
+    config::Policy,
+    cookie_fetch::{Aborts, BodyStreams, FetchError, UploadStreams},
+    session::Sessions,
+    CookieClient, CookieClientPool,
+};
+use std::sync::Arc;
 
 pub struct CookieFetchState {
     pub client_pool: CookieClientPool,
+    pub sessions: Sessions,
+    pub body_streams: BodyStreams,
+    pub upload_streams: UploadStreams,
+    pub aborts: Aborts,
+    /// `Config.cache`が指定された場合のみ存在する。
+    pub cache: Option<HttpCache>,
     pub config: crate::config::Config,
 }
+
+impl CookieFetchState {
+    /// `window`に許可されたセッションを取得する。
+    pub fn session(&self, window: &str, id: &str) -> Result<Arc<CookieClient>, FetchError> {
+        self.session_with(&self.config.policy(window), id)
+    }
+
+    pub fn session_with(&self, policy: &Policy, id: &str) -> Result<Arc<CookieClient>, FetchError> {
+        policy.check_session(id)?;
+        self.sessions
+            .get(id)
+            .ok_or_else(|| FetchError::SessionNotFound(id.to_string()))
+    }
+}
@@ -0,0 +1,483 @@
+mod pinning;
+mod spki;
+
+pub(crate) use pinning::pin_mismatch;
+
+use crate::{cookie_fetch::FetchError, HostPattern};
+use base64::Engine;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+/// TLSの設定。証明書などは起動時に読み込まれ、不正な場合はプラグインの初期化が失敗する。
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TlsConfig {
+    /// システムの証明書に加えて信頼するルート証明書。
+    #[serde(default)]
+    pub root_certificates: Vec<Material>,
+    /// mTLSで提示するクライアント証明書。
+    #[serde(default)]
+    pub identity: Option<ClientIdentity>,
+    /// native-tlsは最低バージョンに1.3を指定できないため、`1.3`は読み込み時に拒否する。
+    #[serde(default)]
+    pub min_version: Option<TlsVersion>,
+    /// ホストごとに許可する公開鍵。`sha256/`に続けて、SubjectPublicKeyInfoのSHA-256をbase64で指定する。
+    ///
+    /// 指定した場合はハンドシェイクの中で照合するため、rustlsで接続する。TLS 1.2以上のみを用い、`identity`にPKCS#12は使えない。
+    ///
+    /// ホストには`scope`と同じく`*.example.com`の形式を使える。複数の指定に一致する場合は、完全一致、長いドメインの順に優先する。
+    #[serde(default)]
+    pub pins: HashMap<String, Vec<String>>,
+}
+
+/// PEMの内容、もしくはファイルのパス。相対パスはプロセスの作業ディレクトリから解決する。
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Material {
+    Pem(String),
+    Path(PathBuf),
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", tag = "format")]
+pub enum ClientIdentity {
+    /// 証明書と、PKCS#8のPEMの秘密鍵。
+    Pem {
+        certificate: Material,
+        key: Material,
+    },
+    Pkcs12 {
+        path: PathBuf,
+        #[serde(default)]
+        password: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls1_0,
+    #[serde(rename = "1.1")]
+    Tls1_1,
+    #[serde(rename = "1.2")]
+    Tls1_2,
+    #[serde(rename = "1.3")]
+    Tls1_3,
+}
+
+impl std::fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TlsVersion::Tls1_0 => "1.0",
+            TlsVersion::Tls1_1 => "1.1",
+            TlsVersion::Tls1_2 => "1.2",
+            TlsVersion::Tls1_3 => "1.3",
+        })
+    }
+}
+
+/// ホストごとのピン。優先する順に並べる。
+type Pins = Vec<(HostPattern, Vec<Vec<u8>>)>;
+
+/// 読み込み済みのTLSの設定。
+#[derive(Debug, Clone, Default)]
+pub struct Tls {
+    root_certificates: Vec<reqwest::Certificate>,
+    identity: Option<reqwest::Identity>,
+    min_version: Option<reqwest::tls::Version>,
+    pins: Pins,
+    /// ピンを照合する接続の設定。`pins`が空の場合は`None`。
+    pinned: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl Material {
+    fn read(&self) -> Result<Vec<u8>, TlsError> {
+        match self {
+            Material::Pem(pem) => Ok(pem.as_bytes().to_vec()),
+            Material::Path(path) => read(path),
+        }
+    }
+}
+
+fn read(path: &PathBuf) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|e| TlsError::Io(path.clone(), e))
+}
+
+impl TlsConfig {
+    pub fn load(&self) -> Result<Tls, TlsError> {
+        let mut root_certificates = Vec::new();
+        for material in &self.root_certificates {
+            let certs = reqwest::Certificate::from_pem_bundle(&material.read()?)
+                .map_err(TlsError::Certificate)?;
+            if certs.is_empty() {
+                return Err(TlsError::NoCertificate);
+            }
+            root_certificates.extend(certs);
+        }
+
+        let identity = match &self.identity {
+            Some(ClientIdentity::Pem { certificate, key }) => Some(
+                reqwest::Identity::from_pkcs8_pem(&certificate.read()?, &key.read()?)
+                    .map_err(TlsError::Identity)?,
+            ),
+            Some(ClientIdentity::Pkcs12 { path, password }) => Some(
+                reqwest::Identity::from_pkcs12_der(&read(path)?, password)
+                    .map_err(TlsError::Identity)?,
+            ),
+            None => None,
+        };
+
+        let min_version = match self.min_version {
+            None => None,
+            Some(TlsVersion::Tls1_0) => Some(reqwest::tls::Version::TLS_1_0),
+            Some(TlsVersion::Tls1_1) => Some(reqwest::tls::Version::TLS_1_1),
+            Some(TlsVersion::Tls1_2) => Some(reqwest::tls::Version::TLS_1_2),
+            Some(TlsVersion::Tls1_3) => {
+                return Err(TlsError::UnsupportedVersion(TlsVersion::Tls1_3))
+            }
+        };
+
+        let mut pins: Pins = Vec::new();
+        for (host, values) in &self.pins {
+            let host = HostPattern::parse(host);
+            let values = values
+                .iter()
+                .map(|p| parse_pin(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            // 大文字小文字だけが異なるホストは同じ指定としてまとめる。
+            match pins.iter_mut().find(|(h, _)| *h == host) {
+                Some((_, existing)) => existing.extend(values),
+                None => pins.push((host, values)),
+            }
+        }
+        pins.sort_by_key(|(host, _)| specificity(host));
+
+        let pinned = match pins.is_empty() {
+            true => None,
+            false => Some(Arc::new(self.pinned_config(pins.clone())?)),
+        };
+
+        Ok(Tls {
+            root_certificates,
+            identity,
+            min_version,
+            pins,
+            pinned,
+        })
+    }
+
+    /// システムの証明書と`root_certificates`を信頼し、`pins`を照合するrustlsの設定を作る。
+    fn pinned_config(&self, pins: Pins) -> Result<rustls::ClientConfig, TlsError> {
+        let mut roots = rustls::RootCertStore::empty();
+        // 読めないシステムの証明書は、native-tlsと同じく無視する。
+        if let Ok(certs) = rustls_native_certs::load_native_certs() {
+            let certs: Vec<_> = certs.into_iter().map(|c| c.0).collect();
+            roots.add_parsable_certificates(&certs);
+        }
+        for material in &self.root_certificates {
+            for der in pem_certificates(&material.read()?)? {
+                roots
+                    .add(&rustls::Certificate(der))
+                    .map_err(TlsError::Rustls)?;
+            }
+        }
+
+        let verifier = pinning::PinningVerifier {
+            inner: rustls::client::WebPkiVerifier::new(roots, None),
+            pins,
+        };
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+
+        match &self.identity {
+            None => Ok(builder.with_no_client_auth()),
+            Some(ClientIdentity::Pem { certificate, key }) => {
+                let chain = pem_certificates(&certificate.read()?)?
+                    .into_iter()
+                    .map(rustls::Certificate)
+                    .collect();
+                let key = rustls_pemfile::pkcs8_private_keys(&mut key.read()?.as_slice())
+                    .ok()
+                    .and_then(|keys| keys.into_iter().next())
+                    .ok_or(TlsError::NoPrivateKey)?;
+                builder
+                    .with_client_auth_cert(chain, rustls::PrivateKey(key))
+                    .map_err(TlsError::Rustls)
+            }
+            Some(ClientIdentity::Pkcs12 { .. }) => Err(TlsError::Pkcs12WithPins),
+        }
+    }
+}
+
+fn pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>, TlsError> {
+    match rustls_pemfile::certs(&mut &pem[..]) {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Err(TlsError::NoCertificate),
+    }
+}
+
+/// 小さいほど優先する。
+fn specificity(host: &HostPattern) -> (u8, std::cmp::Reverse<usize>) {
+    match host {
+        HostPattern::Exact(_) => (0, std::cmp::Reverse(0)),
+        HostPattern::Subdomains(domain) => (1, std::cmp::Reverse(domain.len())),
+        HostPattern::Any => (2, std::cmp::Reverse(0)),
+    }
+}
+
+fn parse_pin(pin: &str) -> Result<Vec<u8>, TlsError> {
+    pin.strip_prefix("sha256/")
+        .and_then(|p| base64::engine::general_purpose::STANDARD.decode(p).ok())
+        .filter(|p| p.len() == 32)
+        .ok_or_else(|| TlsError::InvalidPin(pin.to_string()))
+}
+
+impl Tls {
+    /// `builder`に証明書などを設定する。
+    pub(crate) fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(config) = &self.pinned {
+            return builder.use_preconfigured_tls(rustls::ClientConfig::clone(config));
+        }
+
+        for cert in &self.root_certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        if let Some(version) = self.min_version {
+            builder = builder.min_tls_version(version);
+        }
+
+        builder
+    }
+
+    #[cfg(test)]
+    fn pins_for(&self, host: &str) -> Option<&[Vec<u8>]> {
+        pins_for(&self.pins, host)
+    }
+
+    /// ピンを設定したホストへは、照合できない`http://`で送らない。
+    pub(crate) fn check_plaintext(&self, url: &reqwest::Url) -> Result<(), FetchError> {
+        match url.host_str() {
+            Some(host) if url.scheme() == "http" && pins_for(&self.pins, host).is_some() => {
+                Err(FetchError::CertificatePinMismatch(host.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn pins_for<'a>(pins: &'a Pins, host: &str) -> Option<&'a [Vec<u8>]> {
+    pins.iter()
+        .find(|(h, _)| h.matches(host))
+        .map(|(_, pins)| pins.as_slice())
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, std::io::Error),
+    Certificate(reqwest::Error),
+    /// PEMに証明書が一つも含まれていない。
+    NoCertificate,
+    Identity(reqwest::Error),
+    InvalidPin(String),
+    UnsupportedVersion(TlsVersion),
+    /// ピンを照合する接続の設定に失敗した。
+    Rustls(rustls::Error),
+    /// クライアント証明書のPEMにPKCS#8の秘密鍵が含まれていない。
+    NoPrivateKey,
+    Pkcs12WithPins,
+}
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(path, e) => write!(f, "failed to read `{}`: {}", path.display(), e),
+            TlsError::Certificate(e) => write!(f, "invalid root certificate: {}", e),
+            TlsError::NoCertificate => f.write_str("root certificate PEM contains no certificate"),
+            TlsError::Identity(e) => write!(f, "invalid client identity: {}", e),
+            TlsError::InvalidPin(pin) => write!(
+                f,
+                "invalid pin `{}`: expected `sha256/` followed by a base64 SHA-256 digest",
+                pin
+            ),
+            TlsError::UnsupportedVersion(version) => {
+                write!(f, "minimum TLS version {} is not supported", version)
+            }
+            TlsError::Rustls(e) => write!(f, "invalid TLS configuration for pinning: {}", e),
+            TlsError::NoPrivateKey => f.write_str("client identity PEM contains no PKCS#8 key"),
+            TlsError::Pkcs12WithPins => {
+                f.write_str("a PKCS#12 client identity cannot be used together with pins")
+            }
+        }
+    }
+}
+impl std::error::Error for TlsError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cookie_client::ClientConfig, CookieClient};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const CA: &str = include_str!("testdata/ca.pem");
+    const SERVER_CERT: &[u8] = include_bytes!("testdata/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("testdata/server.key");
+    /// `openssl x509 -pubkey -noout -in server.pem | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`
+    const SERVER_PIN: &str = "sha256/Y5WiBY5fwe13EdTsCTQ8ZUcUbx3sTgweN7+1SFVp9sY=";
+
+    /// `connections`個の接続を受け付け、それぞれに`ok`と応答するHTTPSサーバ。受け取ったリクエストの数も返す。
+    fn serve(connections: usize) -> (String, Arc<AtomicUsize>) {
+        let identity = native_tls::Identity::from_pkcs8(SERVER_CERT, SERVER_KEY).unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let received = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let Ok(mut stream) = acceptor.accept(stream.unwrap()) else {
+                    continue;
+                };
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                received.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                );
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn get(config: TlsConfig, url: &str) -> Result<(), FetchError> {
+        let config = ClientConfig {
+            tls: config.load().unwrap(),
+            ..Default::default()
+        };
+        let client =
+            CookieClient::with_config(reqwest_cookie_store::CookieStore::new(None), &config)
+                .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(client.request(reqwest::Method::GET, url).send())
+            .map(drop)
+            .map_err(FetchError::from_reqwest)
+    }
+
+    fn trusting_ca() -> TlsConfig {
+        TlsConfig {
+            root_certificates: vec![Material::Pem(CA.to_string())],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trust_extra_root_certificate() {
+        let (url, _) = serve(2);
+        assert!(get(TlsConfig::default(), &url).is_err());
+        assert!(get(trusting_ca(), &url).is_ok());
+    }
+
+    #[test]
+    fn verify_pins_during_handshake() {
+        let (url, requests) = serve(2);
+
+        let mut config = trusting_ca();
+        config
+            .pins
+            .insert("localhost".to_string(), vec![SERVER_PIN.to_string()]);
+        assert!(get(config, &url).is_ok());
+
+        let mut config = trusting_ca();
+        let other = format!("sha256/{}", "A".repeat(43) + "=");
+        config.pins.insert("localhost".to_string(), vec![other]);
+        assert!(matches!(
+            get(config, &url),
+            Err(FetchError::CertificatePinMismatch(host)) if host == "localhost"
+        ));
+        // 一致しなかった接続ではリクエストを送っていない。
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let mut config = trusting_ca();
+        config
+            .pins
+            .insert("localhost".to_string(), vec![SERVER_PIN.to_string()]);
+        let tls = config.load().unwrap();
+        let url = reqwest::Url::parse("http://localhost/").unwrap();
+        assert!(matches!(
+            tls.check_plaintext(&url),
+            Err(FetchError::CertificatePinMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_material() {
+        let config = TlsConfig {
+            root_certificates: vec![Material::Pem("not a certificate".to_string())],
+            ..Default::default()
+        };
+        assert!(config.load().is_err());
+
+        let config = TlsConfig {
+            pins: HashMap::from([("localhost".to_string(), vec!["sha256/short".to_string()])]),
+            ..Default::default()
+        };
+        assert!(matches!(config.load(), Err(TlsError::InvalidPin(_))));
+
+        let config = TlsConfig {
+            min_version: Some(TlsVersion::Tls1_3),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.load(),
+            Err(TlsError::UnsupportedVersion(TlsVersion::Tls1_3))
+        ));
+    }
+
+    #[test]
+    fn prefer_specific_pins() {
+        let pin = |b: u8| {
+            format!(
+                "sha256/{}",
+                base64::engine::general_purpose::STANDARD.encode([b; 32])
+            )
+        };
+        let config = TlsConfig {
+            pins: HashMap::from([
+                ("*".to_string(), vec![pin(0)]),
+                ("*.example.com".to_string(), vec![pin(1)]),
+                ("*.api.example.com".to_string(), vec![pin(2)]),
+                ("v1.api.example.com".to_string(), vec![pin(3)]),
+            ]),
+            ..Default::default()
+        };
+        let tls = config.load().unwrap();
+
+        let first = |host: &str| tls.pins_for(host).map(|p| p[0][0]);
+        assert_eq!(first("v1.api.example.com"), Some(3));
+        assert_eq!(first("v2.api.example.com"), Some(2));
+        assert_eq!(first("www.example.com"), Some(1));
+        assert_eq!(first("example.org"), Some(0));
+    }
+}
@@ -0,0 +1,100 @@
+use super::{spki, Pins};
+use rustls::{
+    client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, CertificateError, DigitallySignedStruct, ServerName, SignatureScheme,
+};
+use sha2::Digest;
+use std::{sync::Arc, time::SystemTime};
+
+/// 通常の検証に加えて、ハンドシェイクの中でサーバの公開鍵をピンと照合する。
+///
+/// 一致しない場合は接続を確立せず、リクエストは送られない。
+pub(super) struct PinningVerifier {
+    pub(super) inner: WebPkiVerifier,
+    pub(super) pins: Pins,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => return Ok(verified),
+        };
+        let Some(pins) = super::pins_for(&self.pins, &host) else {
+            return Ok(verified);
+        };
+
+        let digest = spki::subject_public_key_info(&end_entity.0).map(sha2::Sha256::digest);
+        match digest {
+            Some(digest) if pins.iter().any(|p| p.as_slice() == digest.as_slice()) => Ok(verified),
+            _ => Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                Arc::new(PinMismatch(host)),
+            ))),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// サーバの公開鍵がピンに一致しなかった。
+#[derive(Debug)]
+struct PinMismatch(String);
+
+impl std::fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "certificate of `{}` does not match the pinned keys",
+            self.0
+        )
+    }
+}
+impl std::error::Error for PinMismatch {}
+
+/// `e`がピンの不一致によるものであれば、そのホストを返す。
+pub(crate) fn pin_mismatch(e: &reqwest::Error) -> Option<String> {
+    crate::cookie_fetch::causes(e).find_map(|s| match s.downcast_ref::<rustls::Error>()? {
+        rustls::Error::InvalidCertificate(CertificateError::Other(other)) => other
+            .downcast_ref::<PinMismatch>()
+            .map(|PinMismatch(host)| host.clone()),
+        _ => None,
+    })
+}
@@ -0,0 +1,52 @@
+const SEQUENCE: u8 = 0x30;
+/// `tbsCertificate.version`の`[0] EXPLICIT`。
+const VERSION: u8 = 0xa0;
+
+/// DERの要素を一つ読み、タグ、内容、残りを返す。
+fn element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(n);
+        let len = bytes.iter().fold(0, |acc, b| acc << 8 | *b as usize);
+        (len, rest)
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    let (content, rest) = rest.split_at(len);
+    Some((tag, content, rest))
+}
+
+fn sequence(input: &[u8]) -> Option<&[u8]> {
+    match element(input)? {
+        (SEQUENCE, content, _) => Some(content),
+        _ => None,
+    }
+}
+
+/// DERの証明書から`subjectPublicKeyInfo`を、タグと長さを含めたまま取り出す。
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let certificate = sequence(certificate)?;
+    let mut tbs = sequence(certificate)?;
+
+    if tbs.first() == Some(&VERSION) {
+        tbs = element(tbs)?.2;
+    }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        tbs = element(tbs)?.2;
+    }
+
+    match element(tbs)? {
+        (SEQUENCE, _, rest) => Some(&tbs[..tbs.len() - rest.len()]),
+        _ => None,
+    }
+}